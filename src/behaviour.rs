@@ -0,0 +1,24 @@
+use libp2p::identity::Keypair;
+use libp2p::swarm::NetworkBehaviour;
+use libp2p::{identify, ping};
+
+use crate::admission::Admission;
+use crate::keep_alive::KeepAlive;
+
+/// The agent version this node reports through identify.
+pub const AGENT_VERSION: &str = concat!("libp2p_bug_example/", env!("CARGO_PKG_VERSION"));
+
+/// The behaviour of both sides. Besides the request-response protocol, it runs identify and ping
+/// like a production node does.
+#[derive(NetworkBehaviour)]
+pub struct Behaviour {
+    pub request_response: KeepAlive,
+    pub identify: identify::Behaviour,
+    pub ping: ping::Behaviour,
+    pub admission: Admission,
+}
+
+pub fn identify_config(key: &Keypair) -> identify::Config {
+    identify::Config::new("/libp2p_bug_example/1.0.0".to_owned(), key.public())
+        .with_agent_version(AGENT_VERSION.to_owned())
+}
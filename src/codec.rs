@@ -0,0 +1,405 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use clap::ValueEnum;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use unsigned_varint::io::ReadError;
+
+/// How the response is laid out on the wire.
+#[derive(Clone, Copy, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Framing {
+    /// The requested amount of 1KB chunks, with nothing else.
+    #[default]
+    Raw,
+    /// A varint length, pseudo-random contents of that length, and a CRC32 of the contents.
+    /// Truncated or corrupted responses are reported as `InvalidData` errors.
+    Checksummed,
+}
+
+/// Largest request payload the receiving side accepts, so that a request can't make it allocate
+/// an arbitrary amount of memory.
+pub const MAX_REQUEST_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A request for a response of `response_size_in_kilobyte` 1KB messages, uploading `payload` on
+/// the way.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub response_size_in_kilobyte: u64,
+    pub payload: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(request_size_in_kilobyte: u64, response_size_in_kilobyte: u64) -> Self {
+        Self {
+            response_size_in_kilobyte,
+            payload: vec![1u8; request_size_in_kilobyte as usize * 1024],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Response {
+    pub size_in_kilobyte: u64,
+    /// When the response arrived. Only set on the side that read it.
+    pub arrival: Option<Arrival>,
+}
+
+impl Response {
+    pub fn new(size_in_kilobyte: u64) -> Self {
+        Self { size_in_kilobyte, arrival: None }
+    }
+}
+
+/// When the bytes of a response arrived, as measured while reading it.
+#[derive(Clone, Copy, Debug)]
+pub struct Arrival {
+    pub first_byte: Instant,
+    pub last_byte: Instant,
+    pub bytes: u64,
+}
+
+/// How much of the response being read arrived so far. With several responses in flight, this is
+/// the one that most recently received bytes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Progress {
+    pub bytes: u64,
+    /// Time since the reading side started waiting for the response.
+    pub elapsed: Duration,
+}
+
+/// How fast a side goes through a response, one 1KB chunk at a time.
+#[derive(Clone, Copy, Debug)]
+pub enum Throttle {
+    /// A token bucket: `bytes_per_sec` on average, and up to `burst_bytes` at once after a pause.
+    RateLimit { bytes_per_sec: u64, burst_bytes: u64 },
+    /// A fixed pause before every chunk.
+    ChunkDelay(Duration),
+}
+
+/// Paces the chunks of one response according to a throttle. Uses the time of tokio, so that it
+/// follows paused time.
+struct Pacer {
+    throttle: Option<Throttle>,
+    /// Bytes that can go without waiting. Negative while waiting for the last chunk's bytes.
+    tokens: f64,
+    refilled: tokio::time::Instant,
+}
+
+impl Pacer {
+    fn new(throttle: Option<Throttle>) -> Self {
+        let tokens = match throttle {
+            Some(Throttle::RateLimit { burst_bytes, .. }) => burst_bytes as f64,
+            _ => 0.0,
+        };
+        Self { throttle, tokens, refilled: tokio::time::Instant::now() }
+    }
+
+    /// Waits until a chunk of `chunk_size` bytes may go.
+    async fn pace(&mut self, chunk_size: usize) {
+        match self.throttle {
+            None => {}
+            Some(Throttle::ChunkDelay(delay)) => tokio::time::sleep(delay).await,
+            Some(Throttle::RateLimit { bytes_per_sec, burst_bytes }) => {
+                let bytes_per_sec = bytes_per_sec.max(1) as f64;
+                let now = tokio::time::Instant::now();
+                self.tokens = (self.tokens + (now - self.refilled).as_secs_f64() * bytes_per_sec)
+                    .min(burst_bytes as f64);
+                self.refilled = now;
+                self.tokens -= chunk_size as f64;
+                if self.tokens < 0.0 {
+                    tokio::time::sleep(Duration::from_secs_f64(-self.tokens / bytes_per_sec)).await;
+                }
+            }
+        }
+    }
+}
+
+/// Wraps a stream and records when bytes were read from it.
+struct ArrivalRecorder<'a, T> {
+    inner: &'a mut T,
+    arrival: Option<Arrival>,
+    started: Instant,
+    progress: Option<&'a watch::Sender<Progress>>,
+}
+
+impl<'a, T> ArrivalRecorder<'a, T> {
+    fn new(inner: &'a mut T, progress: Option<&'a watch::Sender<Progress>>) -> Self {
+        Self { inner, arrival: None, started: Instant::now(), progress }
+    }
+}
+
+impl<T> AsyncRead for ArrivalRecorder<'_, T>
+where
+    T: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut *self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            if n > 0 {
+                let now = Instant::now();
+                let arrival = self.arrival.get_or_insert(Arrival {
+                    first_byte: now,
+                    last_byte: now,
+                    bytes: 0,
+                });
+                arrival.last_byte = now;
+                arrival.bytes += n as u64;
+                let bytes = arrival.bytes;
+                if let Some(progress) = self.progress {
+                    progress.send_replace(Progress { bytes, elapsed: now - self.started });
+                }
+            }
+        }
+        poll
+    }
+}
+
+#[derive(Clone)]
+pub struct Codec {
+    message_size_in_kilobyte: u64,
+    framing: Framing,
+    progress: Option<Arc<watch::Sender<Progress>>>,
+    write_throttle: Option<Throttle>,
+    read_throttle: Option<Throttle>,
+}
+
+impl Codec {
+    pub fn new(message_size_in_kilobyte: u64) -> Self {
+        Self {
+            message_size_in_kilobyte,
+            framing: Framing::default(),
+            progress: None,
+            write_throttle: None,
+            read_throttle: None,
+        }
+    }
+
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Publishes the progress of every response read through `progress` as its bytes arrive.
+    pub fn with_progress(mut self, progress: watch::Sender<Progress>) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// Writes every response no faster than `throttle` allows.
+    pub fn with_write_throttle(mut self, throttle: Throttle) -> Self {
+        self.write_throttle = Some(throttle);
+        self
+    }
+
+    /// Reads every response no faster than `throttle` allows, leaving the rest of its bytes waiting
+    /// in the stream.
+    pub fn with_read_throttle(mut self, throttle: Throttle) -> Self {
+        self.read_throttle = Some(throttle);
+        self
+    }
+}
+
+async fn read_varint<T>(io: &mut T) -> io::Result<u64>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    unsigned_varint::aio::read_u64(&mut *io).await.map_err(|error| match error {
+        ReadError::Io(error) => error,
+        error => io::Error::new(io::ErrorKind::InvalidData, error),
+    })
+}
+
+async fn write_varint<T>(io: &mut T, value: u64) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+{
+    io.write_all(unsigned_varint::encode::u64(value, &mut unsigned_varint::encode::u64_buffer()))
+        .await
+}
+
+/// Fills `buffer` from `io`, adding the amount of bytes read to `received` even if the stream
+/// ends before `buffer` is full.
+async fn read_counted<T>(io: &mut T, buffer: &mut [u8], received: &mut u64) -> io::Result<()>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let n = io.read(&mut buffer[filled..]).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        filled += n;
+        *received += n as u64;
+    }
+    Ok(())
+}
+
+/// Reads a checksummed response and returns its length in bytes.
+async fn read_checksummed<T>(io: &mut T, pacer: &mut Pacer) -> io::Result<u64>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    let length = read_varint(io).await?;
+
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = [0u8; 1024];
+    let mut received = 0;
+    while received < length {
+        let chunk_size = (length - received).min(buffer.len() as u64) as usize;
+        let chunk = &mut buffer[..chunk_size];
+        pacer.pace(chunk_size).await;
+        if let Err(error) = read_counted(io, chunk, &mut received).await {
+            if error.kind() != io::ErrorKind::UnexpectedEof {
+                return Err(error);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Response truncated: received {received} out of {length} bytes"),
+            ));
+        }
+        hasher.update(chunk);
+    }
+
+    let mut checksum = [0u8; 4];
+    let mut checksum_received = 0;
+    if let Err(error) = read_counted(io, &mut checksum, &mut checksum_received).await {
+        if error.kind() != io::ErrorKind::UnexpectedEof {
+            return Err(error);
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Response truncated: received all {length} bytes but no checksum"),
+        ));
+    }
+    if u32::from_be_bytes(checksum) != hasher.finalize() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Response corrupted: checksum mismatch after {received} bytes"),
+        ));
+    }
+    Ok(length)
+}
+
+async fn write_checksummed<T>(io: &mut T, length: u64, pacer: &mut Pacer) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+{
+    write_varint(io, length).await?;
+
+    let mut hasher = crc32fast::Hasher::new();
+    let mut rng = StdRng::from_entropy();
+    let mut buffer = [0u8; 1024];
+    let mut written = 0;
+    while written < length {
+        let chunk_size = (length - written).min(buffer.len() as u64) as usize;
+        let chunk = &mut buffer[..chunk_size];
+        rng.fill_bytes(chunk);
+        hasher.update(chunk);
+        pacer.pace(chunk_size).await;
+        io.write_all(chunk).await?;
+        written += chunk_size as u64;
+    }
+
+    io.write_all(&hasher.finalize().to_be_bytes()).await
+}
+
+#[async_trait]
+impl request_response::Codec for Codec {
+    type Protocol = String;
+    type Request = Request;
+    type Response = Response;
+
+    async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let response_size_in_kilobyte = read_varint(io).await?;
+        let payload_length = read_varint(io).await?;
+        if payload_length > MAX_REQUEST_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Request payload of {payload_length} bytes is above {MAX_REQUEST_SIZE}"),
+            ));
+        }
+        let mut payload = vec![0u8; payload_length as usize];
+        io.read_exact(&mut payload).await?;
+        Ok(Request { response_size_in_kilobyte, payload })
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut io = ArrivalRecorder::new(io, self.progress.as_deref());
+        let mut pacer = Pacer::new(self.read_throttle);
+        let size_in_kilobyte = match self.framing {
+            Framing::Checksummed => read_checksummed(&mut io, &mut pacer).await? / 1024,
+            Framing::Raw => {
+                let mut buffer = [0u8; 1024];
+                for _ in 0..self.message_size_in_kilobyte {
+                    pacer.pace(buffer.len()).await;
+                    io.read_exact(&mut buffer).await?;
+                }
+                self.message_size_in_kilobyte
+            }
+        };
+        Ok(Response { size_in_kilobyte, arrival: io.arrival })
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        request: Self::Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_varint(io, request.response_size_in_kilobyte).await?;
+        write_varint(io, request.payload.len() as u64).await?;
+        io.write_all(&request.payload).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        response: Self::Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let mut pacer = Pacer::new(self.write_throttle);
+        if let Framing::Checksummed = self.framing {
+            return write_checksummed(io, response.size_in_kilobyte * 1024, &mut pacer).await;
+        }
+        let buffer = [1u8; 1024];
+        for _ in 0..response.size_in_kilobyte {
+            pacer.pace(buffer.len()).await;
+            io.write_all(&buffer).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
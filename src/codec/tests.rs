@@ -0,0 +1,64 @@
+use std::io;
+
+use futures::io::Cursor;
+
+use super::{read_checksummed, write_checksummed, Pacer};
+
+/// Length of the checksummed responses the tests read.
+const LENGTH: u64 = 4096;
+/// Amount of bytes `LENGTH` takes as a varint.
+const VARINT_LENGTH: usize = 2;
+
+/// Writes a checksummed response of `LENGTH` bytes.
+async fn checksummed_response() -> Vec<u8> {
+    let mut io = Cursor::new(Vec::new());
+    write_checksummed(&mut io, LENGTH, &mut Pacer::new(None))
+        .await
+        .expect("Error while writing to a buffer");
+    io.into_inner()
+}
+
+async fn read(bytes: Vec<u8>) -> io::Result<u64> {
+    read_checksummed(&mut Cursor::new(bytes), &mut Pacer::new(None)).await
+}
+
+/// Asserts that reading `bytes` fails with `InvalidData` and the given message.
+async fn assert_invalid(bytes: Vec<u8>, message: &str) {
+    let error = read(bytes).await.expect_err("Reading an invalid response succeeded");
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(error.to_string(), message);
+}
+
+#[tokio::test]
+async fn complete_response_is_read() {
+    let bytes = checksummed_response().await;
+    assert_eq!(bytes.len(), VARINT_LENGTH + LENGTH as usize + 4);
+    assert_eq!(read(bytes).await.expect("Error while reading a complete response"), LENGTH);
+}
+
+#[tokio::test]
+async fn truncated_contents_are_invalid() {
+    let mut bytes = checksummed_response().await;
+    bytes.truncate(VARINT_LENGTH + 1500);
+    assert_invalid(bytes, "Response truncated: received 1500 out of 4096 bytes").await;
+}
+
+#[tokio::test]
+async fn missing_checksum_is_invalid() {
+    let mut bytes = checksummed_response().await;
+    bytes.truncate(VARINT_LENGTH + LENGTH as usize + 2);
+    assert_invalid(bytes, "Response truncated: received all 4096 bytes but no checksum").await;
+}
+
+#[tokio::test]
+async fn corrupted_contents_are_invalid() {
+    let mut bytes = checksummed_response().await;
+    bytes[VARINT_LENGTH + 2000] ^= 0xff;
+    assert_invalid(bytes, "Response corrupted: checksum mismatch after 4096 bytes").await;
+}
+
+#[tokio::test]
+async fn empty_stream_is_an_unexpected_eof() {
+    let error = read(Vec::new()).await.expect_err("Reading an empty stream succeeded");
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+}
@@ -0,0 +1,34 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use libp2p::identity::Keypair;
+
+/// Writes `bytes` to a new file at `path` that only the current user can read, on Unix.
+fn write_private(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(path)?.write_all(bytes)
+}
+
+/// Loads a protobuf-encoded keypair from `path`. If the file doesn't exist, generates an ed25519
+/// keypair and saves it there.
+pub fn load_or_generate_keypair(path: &Path) -> io::Result<Keypair> {
+    match std::fs::read(path) {
+        Ok(bytes) => Keypair::from_protobuf_encoding(&bytes)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            let key_pair = Keypair::generate_ed25519();
+            let bytes = key_pair
+                .to_protobuf_encoding()
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            write_private(path, &bytes)?;
+            Ok(key_pair)
+        }
+        Err(error) => Err(error),
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use libp2p::core::Endpoint;
+use libp2p::swarm::handler::ConnectionEvent;
+use libp2p::swarm::{
+    ConnectionDenied, ConnectionHandler, ConnectionHandlerEvent, ConnectionId, FromSwarm,
+    NetworkBehaviour, SubstreamProtocol, THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+};
+use libp2p::{request_response, Multiaddr, PeerId};
+
+use crate::codec::{Codec, Request, Response};
+
+type InnerHandler = THandler<request_response::Behaviour<Codec>>;
+
+/// The requests with a peer that weren't answered yet, shared by the behaviour with the handlers
+/// of the peer's connections.
+#[derive(Default)]
+struct Outstanding {
+    requests: AtomicU64,
+    /// Woken once all the requests were answered, so that the connections notice they may be idle.
+    wakers: Mutex<HashMap<ConnectionId, Waker>>,
+}
+
+impl Outstanding {
+    fn add(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn remove(&self) {
+        let previous =
+            self.requests.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |requests| {
+                requests.checked_sub(1)
+            });
+        if previous == Ok(1) {
+            for waker in self.wakers.lock().expect("Poisoned wakers").values() {
+                waker.wake_by_ref();
+            }
+        }
+    }
+
+    fn any(&self) -> bool {
+        self.requests.load(Ordering::Relaxed) > 0
+    }
+}
+
+/// Wraps the request-response behaviour. If enabled, the connections with a peer are kept alive
+/// while a request sent to or received from it wasn't answered yet, regardless of the idle
+/// connection timeout.
+pub struct KeepAlive {
+    inner: request_response::Behaviour<Codec>,
+    enabled: bool,
+    outstanding: HashMap<PeerId, Arc<Outstanding>>,
+}
+
+impl KeepAlive {
+    pub fn new(inner: request_response::Behaviour<Codec>, enabled: bool) -> Self {
+        Self { inner, enabled, outstanding: HashMap::new() }
+    }
+
+    fn handler(
+        &mut self,
+        inner: InnerHandler,
+        peer: PeerId,
+        connection_id: ConnectionId,
+    ) -> KeepAliveHandler {
+        let outstanding = self.enabled.then(|| self.outstanding.entry(peer).or_default().clone());
+        KeepAliveHandler { inner, connection_id, outstanding }
+    }
+
+    /// Counts a request to `peer` that was handed to a connection, or one that was received.
+    fn add_outstanding(&mut self, peer: PeerId) {
+        self.outstanding.entry(peer).or_default().add();
+    }
+
+    /// Counts a request with `peer` that was answered or failed.
+    fn remove_outstanding(&mut self, peer: PeerId) {
+        if let Some(outstanding) = self.outstanding.get(&peer) {
+            outstanding.remove();
+        }
+    }
+}
+
+impl Deref for KeepAlive {
+    type Target = request_response::Behaviour<Codec>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl DerefMut for KeepAlive {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl NetworkBehaviour for KeepAlive {
+    type ConnectionHandler = KeepAliveHandler;
+    type ToSwarm = request_response::Event<Request, Response>;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.inner.handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        let inner = self.inner.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )?;
+        Ok(self.handler(inner, peer, connection_id))
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.inner.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        let inner = self.inner.handle_established_outbound_connection(
+            connection_id,
+            peer,
+            addr,
+            role_override,
+        )?;
+        Ok(self.handler(inner, peer, connection_id))
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        self.inner.on_swarm_event(event)
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.inner.on_connection_handler_event(peer_id, connection_id, event)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        let poll = self.inner.poll(cx);
+        if !self.enabled {
+            return poll;
+        }
+        match &poll {
+            // The behaviour only notifies handlers of requests to send.
+            Poll::Ready(ToSwarm::NotifyHandler { peer_id, .. }) => self.add_outstanding(*peer_id),
+            Poll::Ready(ToSwarm::GenerateEvent(event)) => match event {
+                request_response::Event::Message {
+                    peer,
+                    message: request_response::Message::Request { .. },
+                } => self.add_outstanding(*peer),
+                request_response::Event::Message {
+                    peer,
+                    message: request_response::Message::Response { .. },
+                }
+                | request_response::Event::OutboundFailure { peer, .. }
+                | request_response::Event::InboundFailure { peer, .. }
+                | request_response::Event::ResponseSent { peer, .. } => {
+                    self.remove_outstanding(*peer)
+                }
+            },
+            _ => {}
+        }
+        poll
+    }
+}
+
+/// Keeps its connection alive while the requests with its peer weren't all answered.
+pub struct KeepAliveHandler {
+    inner: InnerHandler,
+    connection_id: ConnectionId,
+    /// `None` if the keep-alive is disabled.
+    outstanding: Option<Arc<Outstanding>>,
+}
+
+impl Drop for KeepAliveHandler {
+    fn drop(&mut self) {
+        if let Some(outstanding) = &self.outstanding {
+            outstanding.wakers.lock().expect("Poisoned wakers").remove(&self.connection_id);
+        }
+    }
+}
+
+impl ConnectionHandler for KeepAliveHandler {
+    type FromBehaviour = <InnerHandler as ConnectionHandler>::FromBehaviour;
+    type ToBehaviour = <InnerHandler as ConnectionHandler>::ToBehaviour;
+    type InboundProtocol = <InnerHandler as ConnectionHandler>::InboundProtocol;
+    type OutboundProtocol = <InnerHandler as ConnectionHandler>::OutboundProtocol;
+    type InboundOpenInfo = <InnerHandler as ConnectionHandler>::InboundOpenInfo;
+    type OutboundOpenInfo = <InnerHandler as ConnectionHandler>::OutboundOpenInfo;
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
+        self.inner.listen_protocol()
+    }
+
+    fn connection_keep_alive(&self) -> bool {
+        self.outstanding.as_ref().is_some_and(|outstanding| outstanding.any())
+            || self.inner.connection_keep_alive()
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<
+        ConnectionHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::ToBehaviour>,
+    > {
+        if let Some(outstanding) = &self.outstanding {
+            outstanding
+                .wakers
+                .lock()
+                .expect("Poisoned wakers")
+                .insert(self.connection_id, cx.waker().clone());
+        }
+        self.inner.poll(cx)
+    }
+
+    fn poll_close(&mut self, cx: &mut Context<'_>) -> Poll<Option<Self::ToBehaviour>> {
+        self.inner.poll_close(cx)
+    }
+
+    fn on_behaviour_event(&mut self, event: Self::FromBehaviour) {
+        self.inner.on_behaviour_event(event)
+    }
+
+    fn on_connection_event(
+        &mut self,
+        event: ConnectionEvent<
+            Self::InboundProtocol,
+            Self::OutboundProtocol,
+            Self::InboundOpenInfo,
+            Self::OutboundOpenInfo,
+        >,
+    ) {
+        self.inner.on_connection_event(event)
+    }
+}
@@ -0,0 +1,14 @@
+pub mod admission;
+pub mod behaviour;
+pub mod codec;
+pub mod identity;
+pub mod keep_alive;
+pub mod load;
+pub mod logging;
+pub mod mitigation;
+pub mod node;
+pub mod proxy;
+pub mod scenario;
+pub mod stats;
+pub mod sweep;
+pub mod verdict;
@@ -0,0 +1,206 @@
+use std::time::{Duration, Instant};
+
+use libp2p::identity::Keypair;
+use serde::Serialize;
+use tokio::sync::oneshot;
+use tracing::{info_span, Instrument};
+
+use crate::codec::{Codec, Request, MAX_REQUEST_SIZE};
+use crate::mitigation::Mitigations;
+use crate::node::{
+    build_swarm, run_node, spawn_receiver, Sender, StopConditions, Transport, Workload,
+};
+use crate::verdict::{OutputFormat, Verdict};
+
+#[derive(clap::Args)]
+pub struct LoadArgs {
+    /// Amount of sending sides that send their workload to the one receiving side at once.
+    #[arg(short, long, default_value_t = 8, value_parser = clap::value_parser!(u64).range(1..))]
+    clients: u64,
+
+    /// Amount of 1KB messages in each response.
+    #[arg(short, long, default_value_t = 1024)]
+    message_size_in_kilobyte: u64,
+
+    /// Amount of 1KB messages each request uploads. At most 65536, the largest request the
+    /// receiving side accepts.
+    #[arg(
+        short,
+        long,
+        default_value_t = 0,
+        value_parser = clap::value_parser!(u64).range(..=MAX_REQUEST_SIZE / 1024)
+    )]
+    request_size_in_kilobyte: u64,
+
+    /// Amount of requests every sending side sends.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    requests: u64,
+
+    /// Maximal amount of requests every sending side keeps in flight at once.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    concurrency: u64,
+
+    /// Amount of time to wait on idle connection.
+    #[arg(short = 't', long, default_value_t = 100)]
+    idle_connection_timeout_millis: u64,
+
+    /// Amount of time every sending side waits for the outcome before giving up.
+    #[arg(long, default_value_t = 60000)]
+    run_timeout_millis: u64,
+
+    /// Format in which the results are printed.
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+}
+
+/// The outcome of one of the sending sides.
+#[derive(Debug, Serialize)]
+pub struct ClientVerdict {
+    pub client: u64,
+    #[serde(flatten)]
+    pub verdict: Verdict,
+}
+
+/// The outcome of many sending sides against one receiving side.
+#[derive(Debug, Serialize)]
+pub struct LoadReport {
+    pub clients: Vec<ClientVerdict>,
+    /// Bytes of the responses that arrived in full, on all the sending sides.
+    pub total_bytes: u64,
+    /// Time until every sending side knew its outcome.
+    pub elapsed_millis: f64,
+    pub throughput_mb_per_sec: f64,
+    /// Responses the receiving side sent. `None` if it didn't stop within the run timeout once the
+    /// sending sides were done.
+    pub responses_sent: Option<u64>,
+    /// Requests the receiving side failed to answer. `None` in the same case.
+    pub inbound_failures: Option<u64>,
+}
+
+impl LoadReport {
+    pub fn bug_occurred(&self) -> bool {
+        self.clients.iter().any(|client| client.verdict.bug_occurred())
+    }
+
+    /// 1 if the bug occurred on any sending side, and otherwise the first exit code that isn't 0.
+    pub fn exit_code(&self) -> i32 {
+        if self.bug_occurred() {
+            return 1;
+        }
+        self.clients
+            .iter()
+            .map(|client| client.verdict.exit_code())
+            .find(|exit_code| *exit_code != 0)
+            .unwrap_or(0)
+    }
+
+    pub fn report(&self, output: OutputFormat) {
+        match output {
+            OutputFormat::Text => {
+                for client in &self.clients {
+                    println!("Client {}: {}", client.client, client.verdict.message());
+                }
+                println!(
+                    "{} bytes in {:.1}ms, {:.2} MB/s",
+                    self.total_bytes, self.elapsed_millis, self.throughput_mb_per_sec
+                );
+                match (self.responses_sent, self.inbound_failures) {
+                    (Some(responses_sent), Some(inbound_failures)) => println!(
+                        "The receiving side sent {responses_sent} responses and failed to answer \
+                         {inbound_failures} requests"
+                    ),
+                    _ => println!(
+                        "The receiving side didn't stop in time, so its counts are unknown"
+                    ),
+                }
+            }
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string(self).expect("Error while serializing the load report")
+            ),
+        }
+    }
+}
+
+/// Runs `args.clients` sending swarms against one receiving swarm in this process, connected over
+/// an in-memory transport, until every sending side knows whether the bug occurred.
+pub async fn run_load(args: &LoadArgs) -> LoadReport {
+    let codec = Codec::new(args.message_size_in_kilobyte);
+    let idle_connection_timeout = Duration::from_millis(args.idle_connection_timeout_millis);
+    let run_timeout = Duration::from_millis(args.run_timeout_millis);
+    let workload = Workload {
+        request: Request::new(args.request_size_in_kilobyte, args.message_size_in_kilobyte),
+        requests: args.requests,
+        concurrency: args.concurrency,
+    };
+
+    let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+    let (listen_address, mut receiver_task) = spawn_receiver(
+        codec.clone(),
+        idle_connection_timeout,
+        Mitigations::default(),
+        StopConditions { shutdown: Some(shutdown_receiver), ..Default::default() },
+    )
+    .await;
+
+    let start = Instant::now();
+    let client_tasks: Vec<_> = (1..=args.clients)
+        .map(|client| {
+            let mut swarm = build_swarm(
+                Transport::Memory,
+                Keypair::generate_ed25519(),
+                codec.clone(),
+                idle_connection_timeout,
+                Mitigations::default(),
+            );
+            let mut sender = Sender::new(workload.clone());
+            sender.dial(&mut swarm, vec![listen_address.clone()]).unwrap_or_else(|error| {
+                panic!("Error while dialing {}: {:?}", listen_address, error)
+            });
+            tokio::spawn(
+                async move {
+                    let verdict = tokio::time::timeout(
+                        run_timeout,
+                        run_node(&mut swarm, Some(&mut sender), StopConditions::default()),
+                    )
+                    .await
+                    .unwrap_or_else(|_| sender.timeout_verdict());
+                    let bytes = sender.stats().summary().map_or(0, |summary| summary.total_bytes);
+                    (ClientVerdict { client, verdict }, bytes)
+                }
+                .instrument(info_span!("node", role = "sender", client)),
+            )
+        })
+        .collect();
+
+    let mut clients = Vec::new();
+    let mut total_bytes = 0;
+    for client_task in client_tasks {
+        let (client, bytes) = client_task.await.expect("A sending side panicked");
+        clients.push(client);
+        total_bytes += bytes;
+    }
+    let elapsed = start.elapsed();
+
+    // The receiving side stops once its pending responses were sent or failed.
+    let _ = shutdown_sender.send(());
+    let (responses_sent, inbound_failures) =
+        match tokio::time::timeout(run_timeout, &mut receiver_task).await {
+            Ok(Ok(Verdict::Served { responses_sent, inbound_failures })) => {
+                (Some(responses_sent), Some(inbound_failures))
+            }
+            _ => (None, None),
+        };
+    receiver_task.abort();
+
+    LoadReport {
+        clients,
+        total_bytes,
+        elapsed_millis: elapsed.as_secs_f64() * 1000.0,
+        throughput_mb_per_sec: total_bytes as f64
+            / 1_000_000.0
+            / elapsed.as_secs_f64().max(f64::EPSILON),
+        responses_sent,
+        inbound_failures,
+    }
+}
@@ -0,0 +1,165 @@
+use clap::ValueEnum;
+use libp2p::request_response::{Event, Message};
+use libp2p::swarm::SwarmEvent;
+use libp2p::{identify, ping};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use tracing_subscriber::fmt::time::uptime;
+use tracing_subscriber::EnvFilter;
+
+use crate::behaviour::{BehaviourEvent, AGENT_VERSION};
+
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Installs a subscriber that logs to stderr, timestamped with the time since the process
+/// started. The level defaults to info and can be overridden with `RUST_LOG`.
+pub fn init_logging(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_timer(uptime())
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+/// Logs `event` with the connection and peer it concerns.
+pub fn log_swarm_event(event: &SwarmEvent<BehaviourEvent>) {
+    match event {
+        SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(Event::Message {
+            peer,
+            message: Message::Request { request_id, request, .. },
+        })) => info!(
+            %peer,
+            ?request_id,
+            payload_bytes = request.payload.len(),
+            response_size_in_kilobyte = request.response_size_in_kilobyte,
+            "Request received"
+        ),
+        SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(Event::Message {
+            peer,
+            message: Message::Response { request_id, response },
+        })) => info!(
+            %peer,
+            ?request_id,
+            size_in_kilobyte = response.size_in_kilobyte,
+            "Response received"
+        ),
+        SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(Event::OutboundFailure {
+            peer,
+            request_id,
+            error,
+        })) => {
+            warn!(%peer, ?request_id, %error, "Outbound request failed")
+        }
+        SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(Event::InboundFailure {
+            peer,
+            request_id,
+            error,
+        })) => {
+            warn!(%peer, ?request_id, %error, "Inbound request failed")
+        }
+        SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(Event::ResponseSent {
+            peer,
+            request_id,
+        })) => {
+            info!(%peer, ?request_id, "Response sent")
+        }
+        SwarmEvent::Behaviour(BehaviourEvent::Ping(ping::Event { peer, connection, result })) => {
+            match result {
+                Ok(rtt) => info!(%peer, connection_id = ?connection, ?rtt, "Ping"),
+                Err(error) => warn!(%peer, connection_id = ?connection, %error, "Ping failed"),
+            }
+        }
+        SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received {
+            peer_id,
+            info,
+            ..
+        })) => info!(
+            peer = %peer_id,
+            agent_version = %info.agent_version,
+            local_agent_version = AGENT_VERSION,
+            protocol_version = %info.protocol_version,
+            protocols = ?info.protocols,
+            observed_addr = %info.observed_addr,
+            "Identified peer"
+        ),
+        SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Sent {
+            peer_id, ..
+        })) => {
+            info!(peer = %peer_id, agent_version = AGENT_VERSION, "Sent identify info")
+        }
+        SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Error {
+            peer_id,
+            error,
+            ..
+        })) => warn!(peer = %peer_id, %error, "Identify failed"),
+        SwarmEvent::ConnectionEstablished {
+            peer_id,
+            connection_id,
+            endpoint,
+            num_established,
+            established_in,
+            ..
+        } => info!(
+            peer = %peer_id,
+            ?connection_id,
+            address = %endpoint.get_remote_address(),
+            num_established,
+            ?established_in,
+            "Connection established"
+        ),
+        SwarmEvent::ConnectionClosed {
+            peer_id,
+            connection_id,
+            endpoint,
+            num_established,
+            cause,
+        } => info!(
+            peer = %peer_id,
+            ?connection_id,
+            address = %endpoint.get_remote_address(),
+            num_established,
+            ?cause,
+            "Connection closed"
+        ),
+        SwarmEvent::IncomingConnection { connection_id, local_addr, send_back_addr } => info!(
+            ?connection_id,
+            %local_addr,
+            %send_back_addr,
+            "Incoming connection"
+        ),
+        SwarmEvent::IncomingConnectionError { connection_id, send_back_addr, error, .. } => warn!(
+            ?connection_id,
+            %send_back_addr,
+            %error,
+            "Incoming connection failed"
+        ),
+        SwarmEvent::OutgoingConnectionError { connection_id, peer_id, error } => {
+            warn!(?connection_id, peer = ?peer_id, %error, "Outgoing connection failed")
+        }
+        SwarmEvent::Dialing { peer_id, connection_id } => {
+            info!(?connection_id, peer = ?peer_id, "Dialing")
+        }
+        SwarmEvent::NewListenAddr { listener_id, address } => {
+            info!(?listener_id, %address, "Listening")
+        }
+        SwarmEvent::ExpiredListenAddr { listener_id, address } => {
+            info!(?listener_id, %address, "Listen address expired")
+        }
+        SwarmEvent::ListenerClosed { listener_id, addresses, reason } => {
+            info!(?listener_id, ?addresses, ?reason, "Listener closed")
+        }
+        SwarmEvent::ListenerError { listener_id, error } => {
+            warn!(?listener_id, %error, "Listener error")
+        }
+        _ => debug!(?event, "Swarm event"),
+    }
+}
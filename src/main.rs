@@ -1,171 +1,455 @@
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
-use std::{io, iter};
 
-use async_trait::async_trait;
-use clap::Parser;
-use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, StreamExt};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand};
 use libp2p::identity::Keypair;
-use libp2p::swarm::dial_opts::DialOpts;
-use libp2p::swarm::SwarmEvent;
-use libp2p::{noise, request_response, yamux, Multiaddr, SwarmBuilder};
+use libp2p::Multiaddr;
+use libp2p_bug_example::codec::{Codec, Framing, Progress, Request, Throttle, MAX_REQUEST_SIZE};
+use libp2p_bug_example::identity::load_or_generate_keypair;
+use libp2p_bug_example::load::{run_load, LoadArgs};
+use libp2p_bug_example::logging::{init_logging, LogFormat};
+use libp2p_bug_example::mitigation::{run_compare, CompareArgs, Mitigation, Mitigations};
+use libp2p_bug_example::node::{
+    build_swarm, group_by_peer, run_in_process, run_node, Sender, StopConditions, Transport,
+    Workload,
+};
+use libp2p_bug_example::proxy::{run_proxy, ProxyArgs};
+use libp2p_bug_example::scenario::{run_scenario, Scenario};
+use libp2p_bug_example::sweep::{run_sweep, SweepArgs};
+use libp2p_bug_example::verdict::OutputFormat;
+use serde::Serialize;
+use tokio::sync::watch;
 
 /// An executable that sends or receives a lot of bytes.
-#[derive(Parser)]
+///
+/// Every argument can also be given in the `--config` file, under its long name with underscores.
+/// Arguments given on the command line override the file. A flag set in the file is unset with
+/// `--flag=false`.
+#[derive(Parser, Serialize)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Address this node listens on for incoming connections.
+    #[command(subcommand)]
+    #[serde(skip)]
+    command: Option<Command>,
+
+    /// TOML file holding the values of arguments that aren't given on the command line.
+    #[arg(long, global = true)]
+    #[serde(skip)]
+    config: Option<PathBuf>,
+
+    /// Address this node listens on for incoming connections. Can be given multiple times, e.g.
+    /// once for QUIC and once for TCP when running with both transports. Required unless running
+    /// in process.
     #[arg(short, long)]
-    listen_address: String,
+    listen_address: Vec<String>,
 
-    /// Address this node attempts to dial to.
+    /// Address this node attempts to dial to. Can be given multiple times, in which case the
+    /// sending side sends its requests to all the dialed peers at once and reports the outcome
+    /// with each of them. Addresses ending with the same `/p2p/` component are dialed as one peer.
     #[arg(short, long)]
-    dial_address: Option<String>,
+    dial_address: Vec<String>,
 
-    /// Amount of 1KB messages to write or read.
+    /// Amount of 1KB messages in the response. The sending side asks the receiving side for a
+    /// response of this size.
     #[arg(short, long, default_value_t = 1)]
     message_size_in_kilobyte: u64,
 
+    /// Amount of 1KB messages the sending side uploads with its request. At most 65536, the
+    /// largest request the receiving side accepts.
+    #[arg(
+        short,
+        long,
+        default_value_t = 0,
+        value_parser = clap::value_parser!(u64).range(..=MAX_REQUEST_SIZE / 1024)
+    )]
+    request_size_in_kilobyte: u64,
+
+    /// Amount of requests the sending side sends over the connection.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    requests: u64,
+
+    /// Maximal amount of requests the sending side keeps in flight at once.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    concurrency: u64,
+
     /// If active, we're the sending side. If not, we're the receiving side.
-    #[arg(short, long)]
+    #[arg(
+        short,
+        long,
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_value_t = false,
+        default_missing_value = "true"
+    )]
     send_request: bool,
 
     /// Amount of time to wait on idle connection.
     #[arg(short = 't', long, default_value_t = 100)]
     idle_connection_timeout_millis: u64,
-}
 
-#[derive(Clone)]
-pub struct Codec {
-    message_size_in_kilobyte: u64,
+    /// If active, run both the sending and the receiving side in this process over an in-memory
+    /// transport. The listen, dial and send-request arguments are ignored.
+    #[arg(
+        long,
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        require_equals = true,
+        default_value_t = false,
+        default_missing_value = "true"
+    )]
+    in_process: bool,
+
+    /// Amount of receiving sides to run when running in process. The sending side sends its
+    /// requests to all of them at once.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    in_process_receivers: u64,
+
+    /// Amount of time the sending side waits for the outcome before giving up.
+    #[arg(long, default_value_t = 60000)]
+    run_timeout_millis: u64,
+
+    /// If given, the node stops after this amount of time. The receiving side stops accepting
+    /// connections and waits for its pending responses to be sent before exiting.
+    #[arg(long)]
+    max_duration_millis: Option<u64>,
+
+    /// If given, the receiving side stops after sending this amount of responses.
+    #[arg(long)]
+    exit_after_responses: Option<u64>,
+
+    /// If given, the sending side logs how much of the response it read so far at this interval.
+    #[arg(long)]
+    progress_interval_millis: Option<u64>,
+
+    /// Mitigation against the early close to build the swarm with. Can be given multiple times.
+    #[arg(long, value_enum)]
+    mitigation: Vec<Mitigation>,
+
+    /// Request timeout of the request-response behaviour, when that mitigation is used.
+    #[arg(long, default_value_t = 60000)]
+    request_timeout_millis: u64,
+
+    /// How the response is laid out on the wire.
+    #[arg(long, value_enum, default_value_t = Framing::Raw)]
+    framing: Framing,
+
+    /// If given, the receiving side writes responses no faster than this. E.g. 2097152 makes a
+    /// 10MB response take 5 seconds.
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with = "chunk_delay_millis"
+    )]
+    rate_limit_bytes_per_sec: Option<u64>,
+
+    /// Amount of bytes that may go at once with `--rate-limit-bytes-per-sec` or
+    /// `--read-rate-limit-bytes-per-sec`.
+    #[arg(long, default_value_t = 16 * 1024)]
+    rate_limit_burst_bytes: u64,
+
+    /// If given, the receiving side pauses this long before writing every 1KB of a response.
+    #[arg(long, visible_alias = "chunk-delay-ms")]
+    chunk_delay_millis: Option<u64>,
+
+    /// If given, the sending side reads responses no faster than this, so that the stream stalls
+    /// on its side rather than on the receiving side.
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with = "read_chunk_delay_millis"
+    )]
+    read_rate_limit_bytes_per_sec: Option<u64>,
+
+    /// If given, the sending side pauses this long before reading every 1KB of a response.
+    #[arg(long)]
+    read_chunk_delay_millis: Option<u64>,
+
+    /// File holding the protobuf-encoded keypair of this node, so that its PeerId stays the same
+    /// across runs. Generated if missing. Without it, a fresh identity is generated on every run.
+    #[arg(long)]
+    identity_file: Option<PathBuf>,
+
+    /// Transport this node listens and dials over.
+    #[arg(long, value_enum, default_value_t = Transport::Quic)]
+    transport: Transport,
+
+    /// Format of the event log written to stderr.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, global = true)]
+    log_format: LogFormat,
+
+    /// Format in which the verdict of the run is printed.
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
 }
 
-#[async_trait]
-impl request_response::Codec for Codec {
-    type Protocol = String;
-    type Request = ();
-    type Response = ();
-
-    async fn read_request<T>(&mut self, _: &Self::Protocol, _: &mut T) -> io::Result<Self::Request>
-    where
-        T: AsyncRead + Unpin + Send,
-    {
-        Ok(())
-    }
+#[derive(Subcommand)]
+enum Command {
+    /// Runs the in-process exchange over a grid of message sizes and idle connection timeouts.
+    Sweep(SweepArgs),
+    /// Runs the in-process exchange with every mitigation and reports which ones stop the early
+    /// close.
+    CompareMitigations(CompareArgs),
+    /// Runs many sending sides against one receiving side in this process, and reports the
+    /// aggregate throughput, the outcome of every sending side and how many requests the receiving
+    /// side failed to answer.
+    Load(LoadArgs),
+    /// Forwards UDP packets between a dialing side and a listening side, delaying, dropping,
+    /// reordering and duplicating them. For QUIC, have the sending side dial the proxy's address,
+    /// e.g. `/ip4/127.0.0.1/udp/<port>/quic-v1`, instead of the listener's.
+    Proxy(ProxyArgs),
+    /// Prints the PeerId of the keypair in the given file, generating the keypair if missing.
+    Keygen { identity_file: PathBuf },
+    /// Prints the configuration the arguments and the `--config` file add up to, in the format of
+    /// the `--config` file.
+    PrintConfig,
+    /// Runs the steps of a TOML scenario file in this process and checks that every expectation
+    /// holds. Exits with 1 at the first one that doesn't.
+    Scenario { scenario_file: PathBuf },
+}
 
-    async fn read_response<T>(
-        &mut self,
-        _: &Self::Protocol,
-        io: &mut T,
-    ) -> io::Result<Self::Response>
-    where
-        T: AsyncRead + Unpin + Send,
-    {
-        let mut buffer = [0u8; 1024];
-        for _ in 0..self.message_size_in_kilobyte {
-            io.read_exact(&mut buffer).await?;
+/// Turns a value of the `--config` file into the command-line values of argument `key`.
+fn config_values(key: &str, value: &toml::Value) -> Result<Vec<String>, String> {
+    match value {
+        toml::Value::String(value) => Ok(vec![value.clone()]),
+        toml::Value::Integer(value) => Ok(vec![value.to_string()]),
+        toml::Value::Float(value) => Ok(vec![value.to_string()]),
+        toml::Value::Boolean(value) => Ok(vec![value.to_string()]),
+        toml::Value::Array(values) => values.iter().try_fold(Vec::new(), |mut all, value| {
+            match value {
+                toml::Value::Array(_) => return Err(format!("Nested array for `{key}`")),
+                value => all.extend(config_values(key, value)?),
+            }
+            Ok(all)
+        }),
+        toml::Value::Datetime(_) | toml::Value::Table(_) => {
+            Err(format!("Invalid value for `{key}`"))
         }
-        Ok(())
     }
+}
 
-    async fn write_request<T>(
-        &mut self,
-        _: &Self::Protocol,
-        _: &mut T,
-        _: Self::Request,
-    ) -> io::Result<()>
-    where
-        T: AsyncWrite + Unpin + Send,
-    {
-        Ok(())
-    }
+/// Parses the arguments. The ones that weren't given on the command line are taken from the
+/// `--config` file if there is one. The file's values are parsed by clap along with the command
+/// line, so they're validated the same way.
+fn parse_args() -> Args {
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+    let Some(config) = &args.config else {
+        return args;
+    };
+    let config_error = |kind: ErrorKind, message: String| -> ! {
+        Args::command().error(kind, format!("{message} in config {}", config.display())).exit()
+    };
 
-    async fn write_response<T>(
-        &mut self,
-        _: &Self::Protocol,
-        io: &mut T,
-        _: Self::Response,
-    ) -> io::Result<()>
-    where
-        T: AsyncWrite + Unpin + Send,
-    {
-        let buffer = [1u8; 1024];
-        for _ in 0..self.message_size_in_kilobyte {
-            io.write_all(&buffer).await?;
+    let contents = std::fs::read_to_string(config).unwrap_or_else(|error| {
+        config_error(ErrorKind::Io, format!("Error while reading: {error}"))
+    });
+    let file_values: toml::Table = toml::from_str(&contents)
+        .unwrap_or_else(|error| config_error(ErrorKind::InvalidValue, error.to_string()));
+    let serde_json::Value::Object(known_keys) =
+        serde_json::to_value(&args).expect("Error while serializing the arguments")
+    else {
+        unreachable!("Args serializes into a map");
+    };
+
+    let command = Args::command();
+    let mut command_line = vec![std::env::args_os().next().unwrap_or_default()];
+    for (key, value) in &file_values {
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == key.as_str())
+            .filter(|_| known_keys.contains_key(key))
+        else {
+            config_error(ErrorKind::UnknownArgument, format!("Unknown key `{key}`"));
+        };
+        if matches.value_source(key) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let long = arg.get_long().expect("Every argument has a long name");
+        for value in config_values(key, value)
+            .unwrap_or_else(|message| config_error(ErrorKind::InvalidValue, message))
+        {
+            command_line.push(format!("--{long}={value}").into());
         }
-        Ok(())
     }
+    command_line.extend(std::env::args_os().skip(1));
+
+    let matches = Args::command().get_matches_from(command_line);
+    Args::from_arg_matches(&matches).unwrap_or_else(|error| error.exit())
 }
+
 #[tokio::main]
 async fn main() {
-    let args = Args::parse();
-
-    let listen_address = Multiaddr::from_str(&args.listen_address)
-        .expect(&format!("Unable to parse address {}", args.listen_address));
-
-    let key_pair = Keypair::generate_ed25519();
-    let mut swarm = SwarmBuilder::with_existing_identity(key_pair)
-        .with_tokio()
-        .with_quic()
-        .with_behaviour(|_| {
-            request_response::Behaviour::with_codec(
-                Codec { message_size_in_kilobyte: args.message_size_in_kilobyte },
-                iter::once(("/protocol".to_owned(), request_response::ProtocolSupport::Full)),
-                Default::default(),
-            )
-        })
-        .expect("Error while building the swarm")
-        .with_swarm_config(|cfg| {
-            cfg.with_idle_connection_timeout(Duration::from_millis(
-                args.idle_connection_timeout_millis,
-            ))
-        })
-        .build();
-    swarm
-        .listen_on(listen_address)
-        .expect(&format!("Error while binding to {}", args.listen_address));
-
-    if let Some(dial_address_str) = args.dial_address.as_ref() {
-        let dial_address = Multiaddr::from_str(dial_address_str)
-            .expect(&format!("Unable to parse address {}", dial_address_str));
-        swarm
-            .dial(DialOpts::unknown_peer_id().address(dial_address).build())
-            .expect(&format!("Error while dialing {}", dial_address_str));
-    }
-
-    let mut got_response = false;
-
-    while let Some(event) = swarm.next().await {
-        match event {
-            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
-                if args.send_request {
-                    swarm.behaviour_mut().send_request(&peer_id, ());
-                }
-            }
-            SwarmEvent::Behaviour(request_response::Event::Message {
-                message: request_response::Message::Request { channel, .. },
-                ..
-            }) => {
-                swarm.behaviour_mut().send_response(channel, ()).unwrap();
-            }
-            SwarmEvent::Behaviour(request_response::Event::Message {
-                message: request_response::Message::Response { .. },
-                ..
-            }) => {
-                println!(
-                    "The bug did not occur, we got the response. Try to run with a larger message \
-                     or smaller timeout to make the bug occur"
-                );
-                got_response = true;
-            }
-            SwarmEvent::ConnectionClosed { .. } => {
-                if !got_response && args.send_request {
+    let args = parse_args();
+    init_logging(args.log_format);
+    match &args.command {
+        Some(Command::Sweep(sweep_args)) => {
+            run_sweep(sweep_args).await;
+            return;
+        }
+        Some(Command::CompareMitigations(compare_args)) => {
+            run_compare(compare_args).await;
+            return;
+        }
+        Some(Command::Load(load_args)) => {
+            let report = run_load(load_args).await;
+            report.report(load_args.output);
+            std::process::exit(report.exit_code());
+        }
+        Some(Command::Proxy(proxy_args)) => {
+            run_proxy(proxy_args).await.expect("Error while proxying");
+            return;
+        }
+        Some(Command::Scenario { scenario_file }) => {
+            let scenario = Scenario::load(scenario_file).unwrap_or_else(|error| {
+                panic!("Error while loading scenario {}: {:?}", scenario_file.display(), error)
+            });
+            match run_scenario(&scenario).await {
+                Ok(()) => println!("All the steps went as expected"),
+                Err(failure) => {
                     println!(
-                        "The bug occurred! The connection was closed before we got the response"
+                        "Step {} ({:?}) failed: {}",
+                        failure.index, failure.step, failure.message
                     );
+                    std::process::exit(1);
                 }
             }
-            _ => {}
+            return;
+        }
+        Some(Command::PrintConfig) => {
+            print!(
+                "{}",
+                toml::to_string(&args).expect("Error while serializing the configuration")
+            );
+            return;
+        }
+        Some(Command::Keygen { identity_file }) => {
+            let key_pair = load_or_generate_keypair(identity_file).unwrap_or_else(|error| {
+                panic!("Error while loading identity {}: {:?}", identity_file.display(), error)
+            });
+            println!("{}", key_pair.public().to_peer_id());
+            return;
         }
+        None => {}
     }
+    if args.listen_address.is_empty() && !args.in_process {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--listen-address is required unless running with --in-process",
+            )
+            .exit();
+    }
+
+    let idle_connection_timeout = Duration::from_millis(args.idle_connection_timeout_millis);
+    let run_timeout = Duration::from_millis(args.run_timeout_millis);
+    let mitigations =
+        Mitigations::new(&args.mitigation, Duration::from_millis(args.request_timeout_millis));
+    let mut codec = Codec::new(args.message_size_in_kilobyte).with_framing(args.framing);
+    if let Some(bytes_per_sec) = args.rate_limit_bytes_per_sec {
+        codec = codec.with_write_throttle(Throttle::RateLimit {
+            bytes_per_sec,
+            burst_bytes: args.rate_limit_burst_bytes,
+        });
+    }
+    if let Some(chunk_delay_millis) = args.chunk_delay_millis {
+        codec = codec
+            .with_write_throttle(Throttle::ChunkDelay(Duration::from_millis(chunk_delay_millis)));
+    }
+    if let Some(bytes_per_sec) = args.read_rate_limit_bytes_per_sec {
+        codec = codec.with_read_throttle(Throttle::RateLimit {
+            bytes_per_sec,
+            burst_bytes: args.rate_limit_burst_bytes,
+        });
+    }
+    if let Some(read_chunk_delay_millis) = args.read_chunk_delay_millis {
+        codec = codec.with_read_throttle(Throttle::ChunkDelay(Duration::from_millis(
+            read_chunk_delay_millis,
+        )));
+    }
+    let dial_addresses = if args.in_process {
+        Vec::new()
+    } else {
+        group_by_peer(
+            args.dial_address
+                .iter()
+                .map(|dial_address_str| {
+                    Multiaddr::from_str(dial_address_str).unwrap_or_else(|error| {
+                        panic!("Unable to parse address {}: {:?}", dial_address_str, error)
+                    })
+                })
+                .collect(),
+        )
+    };
+    let mut sender = Sender::new(Workload {
+        request: Request::new(args.request_size_in_kilobyte, args.message_size_in_kilobyte),
+        requests: args.requests,
+        concurrency: args.concurrency,
+    })
+    .with_expected_peers(if args.in_process {
+        args.in_process_receivers as usize
+    } else {
+        dial_addresses.len().max(1)
+    });
+    if !args.in_process && args.dial_address.is_empty() {
+        sender = sender.with_inbound_peers();
+    }
+    if let Some(progress_interval_millis) = args.progress_interval_millis {
+        let (progress_sender, progress_receiver) = watch::channel(Progress::default());
+        codec = codec.with_progress(progress_sender);
+        sender = sender
+            .with_progress(progress_receiver, Duration::from_millis(progress_interval_millis));
+    }
+
+    if args.in_process {
+        let verdict = tokio::time::timeout(
+            run_timeout,
+            run_in_process(codec, &mut sender, idle_connection_timeout, mitigations),
+        )
+        .await
+        .unwrap_or_else(|_| sender.timeout_verdict());
+        verdict.report(sender.stats().summary().as_ref(), args.output);
+        std::process::exit(verdict.exit_code());
+    }
+
+    let stop = StopConditions {
+        max_duration: args.max_duration_millis.map(Duration::from_millis),
+        exit_after_responses: args.exit_after_responses,
+        shutdown: None,
+    };
+    let key_pair = match &args.identity_file {
+        Some(identity_file) => load_or_generate_keypair(identity_file).unwrap_or_else(|error| {
+            panic!("Error while loading identity {}: {:?}", identity_file.display(), error)
+        }),
+        None => Keypair::generate_ed25519(),
+    };
+    let mut swarm =
+        build_swarm(args.transport, key_pair, codec, idle_connection_timeout, mitigations);
+    for listen_address_str in &args.listen_address {
+        let listen_address = Multiaddr::from_str(listen_address_str).unwrap_or_else(|error| {
+            panic!("Unable to parse address {}: {:?}", listen_address_str, error)
+        });
+        swarm.listen_on(listen_address).unwrap_or_else(|error| {
+            panic!("Error while binding to {}: {:?}", listen_address_str, error)
+        });
+    }
+
+    for addresses in dial_addresses {
+        sender
+            .dial(&mut swarm, addresses.clone())
+            .unwrap_or_else(|error| panic!("Error while dialing {:?}: {:?}", addresses, error));
+    }
+
+    let verdict = if args.send_request {
+        tokio::time::timeout(run_timeout, run_node(&mut swarm, Some(&mut sender), stop))
+            .await
+            .unwrap_or_else(|_| sender.timeout_verdict())
+    } else {
+        run_node(&mut swarm, None, stop).await
+    };
+    verdict.report(sender.stats().summary().as_ref(), args.output);
+    std::process::exit(verdict.exit_code());
 }
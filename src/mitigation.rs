@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::codec::{Codec, Request};
+use crate::node::{run_in_process, Sender, Workload};
+use crate::sweep::SweepFormat;
+use crate::verdict::Verdict;
+
+/// A way to keep the connection from being closed while a slow response is in flight.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mitigation {
+    /// Ping at half the idle connection timeout instead of every 15 seconds.
+    Ping,
+    /// Set the request timeout of the request-response behaviour.
+    RequestTimeout,
+    /// Keep the connection alive while a request sent or received on it wasn't answered yet.
+    KeepAlive,
+}
+
+impl Mitigation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mitigation::Ping => "ping",
+            Mitigation::RequestTimeout => "request-timeout",
+            Mitigation::KeepAlive => "keep-alive",
+        }
+    }
+}
+
+/// The mitigations a swarm is built with.
+#[derive(Clone, Copy, Default)]
+pub struct Mitigations {
+    pub ping: bool,
+    pub request_timeout: Option<Duration>,
+    pub keep_alive: bool,
+}
+
+impl Mitigations {
+    pub fn new(mitigations: &[Mitigation], request_timeout: Duration) -> Self {
+        Self {
+            ping: mitigations.contains(&Mitigation::Ping),
+            request_timeout: mitigations
+                .contains(&Mitigation::RequestTimeout)
+                .then_some(request_timeout),
+            keep_alive: mitigations.contains(&Mitigation::KeepAlive),
+        }
+    }
+}
+
+#[derive(clap::Args)]
+pub struct CompareArgs {
+    /// Amount of 1KB messages in the response.
+    #[arg(short, long, default_value_t = 1024)]
+    message_size_in_kilobyte: u64,
+
+    /// Amount of time to wait on idle connection.
+    #[arg(short = 't', long, default_value_t = 100)]
+    idle_connection_timeout_millis: u64,
+
+    /// Request timeout of the request-response behaviour, when that mitigation is used.
+    #[arg(long, default_value_t = 60000)]
+    request_timeout_millis: u64,
+
+    /// Amount of time each run waits for the outcome before giving up.
+    #[arg(long, default_value_t = 60000)]
+    run_timeout_millis: u64,
+
+    /// Format in which the results are printed.
+    #[arg(short, long, value_enum, default_value_t = SweepFormat::Table)]
+    format: SweepFormat,
+}
+
+/// The outcome of running with a set of mitigations.
+pub struct Comparison {
+    pub mitigations: String,
+    pub verdict: Verdict,
+}
+
+async fn run_case(args: &CompareArgs, mitigations: &[Mitigation]) -> Verdict {
+    let mut sender = Sender::new(Workload::single(Request::new(0, args.message_size_in_kilobyte)));
+    tokio::time::timeout(
+        Duration::from_millis(args.run_timeout_millis),
+        run_in_process(
+            Codec::new(args.message_size_in_kilobyte),
+            &mut sender,
+            Duration::from_millis(args.idle_connection_timeout_millis),
+            Mitigations::new(mitigations, Duration::from_millis(args.request_timeout_millis)),
+        ),
+    )
+    .await
+    .unwrap_or(Verdict::Timeout)
+}
+
+/// Runs the in-process exchange without mitigations, with every mitigation on its own, and with
+/// all of them.
+pub async fn run_comparison(args: &CompareArgs) -> Vec<Comparison> {
+    let all = Mitigation::value_variants();
+    let mut cases = vec![("none".to_owned(), Vec::new())];
+    cases.extend(all.iter().map(|mitigation| (mitigation.name().to_owned(), vec![*mitigation])));
+    cases.push(("all".to_owned(), all.to_vec()));
+
+    let mut comparisons = Vec::new();
+    for (name, mitigations) in cases {
+        let verdict = run_case(args, &mitigations).await;
+        comparisons.push(Comparison { mitigations: name, verdict });
+    }
+    comparisons
+}
+
+pub async fn run_compare(args: &CompareArgs) {
+    let comparisons = run_comparison(args).await;
+    match args.format {
+        SweepFormat::Table => {
+            println!("{:<16} {:<24} {:<12}", "mitigations", "verdict", "early_close");
+            for comparison in comparisons {
+                println!(
+                    "{:<16} {:<24} {:<12}",
+                    comparison.mitigations,
+                    comparison.verdict.name(),
+                    comparison.verdict.bug_occurred()
+                );
+            }
+        }
+        SweepFormat::Csv => {
+            println!("mitigations,verdict,early_close");
+            for comparison in comparisons {
+                println!(
+                    "{},{},{}",
+                    comparison.mitigations,
+                    comparison.verdict.name(),
+                    comparison.verdict.bug_occurred()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,815 @@
+use std::collections::{HashMap, HashSet};
+use std::iter;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use futures::{future, StreamExt};
+use libp2p::core::transport::{ListenerId, MemoryTransport};
+use libp2p::core::upgrade::Version;
+use libp2p::core::Transport as _;
+use libp2p::identity::Keypair;
+use libp2p::multiaddr::Protocol;
+use libp2p::request_response::{
+    InboundFailure, InboundRequestId, OutboundFailure, OutboundRequestId,
+};
+use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::swarm::{ConnectionError, ConnectionId, DialError, SwarmEvent};
+use libp2p::{
+    identify, noise, ping, request_response, tcp, yamux, Multiaddr, PeerId, Swarm, SwarmBuilder,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, Interval};
+use tracing::{info, info_span, warn, Instrument};
+
+use crate::admission::Admission;
+use crate::behaviour::{identify_config, Behaviour, BehaviourEvent};
+use crate::codec::{Codec, Progress, Request, Response};
+use crate::keep_alive::KeepAlive;
+use crate::logging::log_swarm_event;
+use crate::mitigation::Mitigations;
+use crate::stats::TransferStats;
+use crate::verdict::{describe_close_cause, PeerVerdict, Verdict};
+
+/// The transport a swarm communicates over.
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transport {
+    Quic,
+    /// TCP with Noise encryption and Yamux multiplexing.
+    Tcp,
+    /// Both QUIC and TCP. The address that is dialed picks between them.
+    Both,
+    #[value(skip)]
+    #[serde(skip)]
+    Memory,
+}
+
+pub fn build_swarm(
+    transport: Transport,
+    key_pair: Keypair,
+    codec: Codec,
+    idle_connection_timeout: Duration,
+    mitigations: Mitigations,
+) -> Swarm<Behaviour> {
+    let behaviour = |key: &Keypair| {
+        let mut config = request_response::Config::default();
+        if let Some(request_timeout) = mitigations.request_timeout {
+            config = config.with_request_timeout(request_timeout);
+        }
+        let mut ping_config = ping::Config::new();
+        if mitigations.ping {
+            ping_config = ping_config
+                .with_interval((idle_connection_timeout / 2).max(Duration::from_millis(1)));
+        }
+        Behaviour {
+            request_response: KeepAlive::new(
+                request_response::Behaviour::with_codec(
+                    codec.clone(),
+                    iter::once(("/protocol".to_owned(), request_response::ProtocolSupport::Full)),
+                    config,
+                ),
+                mitigations.keep_alive,
+            ),
+            identify: identify::Behaviour::new(identify_config(key)),
+            ping: ping::Behaviour::new(ping_config),
+            admission: Admission::default(),
+        }
+    };
+    let swarm_config =
+        |cfg: libp2p::swarm::Config| cfg.with_idle_connection_timeout(idle_connection_timeout);
+
+    match transport {
+        Transport::Quic => SwarmBuilder::with_existing_identity(key_pair)
+            .with_tokio()
+            .with_quic()
+            .with_behaviour(behaviour)
+            .expect("Error while building the swarm")
+            .with_swarm_config(swarm_config)
+            .build(),
+        Transport::Tcp => SwarmBuilder::with_existing_identity(key_pair)
+            .with_tokio()
+            .with_tcp(tcp::Config::default(), noise::Config::new, yamux::Config::default)
+            .expect("Error while building the TCP transport")
+            .with_behaviour(behaviour)
+            .expect("Error while building the swarm")
+            .with_swarm_config(swarm_config)
+            .build(),
+        Transport::Both => SwarmBuilder::with_existing_identity(key_pair)
+            .with_tokio()
+            .with_tcp(tcp::Config::default(), noise::Config::new, yamux::Config::default)
+            .expect("Error while building the TCP transport")
+            .with_quic()
+            .with_behaviour(behaviour)
+            .expect("Error while building the swarm")
+            .with_swarm_config(swarm_config)
+            .build(),
+        Transport::Memory => SwarmBuilder::with_existing_identity(key_pair)
+            .with_tokio()
+            .with_other_transport(|key| {
+                MemoryTransport::default()
+                    .upgrade(Version::V1)
+                    .authenticate(
+                        noise::Config::new(key).expect("Error while creating noise config"),
+                    )
+                    .multiplex(yamux::Config::default())
+            })
+            .expect("Error while building the memory transport")
+            .with_behaviour(behaviour)
+            .expect("Error while building the swarm")
+            .with_swarm_config(swarm_config)
+            .build(),
+    }
+}
+
+/// The PeerId in the `/p2p/` component `address` ends with, if any.
+fn peer_id_of(address: &Multiaddr) -> Option<PeerId> {
+    match address.iter().last() {
+        Some(Protocol::P2p(peer_id)) => Some(peer_id),
+        _ => None,
+    }
+}
+
+/// Groups `addresses` by the peer they lead to, so that a peer given at several addresses is dialed
+/// once and counts once. Only the addresses ending with the same `/p2p/` component, or equal ones,
+/// are known to lead to the same peer.
+pub fn group_by_peer(addresses: Vec<Multiaddr>) -> Vec<Vec<Multiaddr>> {
+    let mut groups: Vec<Vec<Multiaddr>> = Vec::new();
+    for address in addresses {
+        let peer_id = peer_id_of(&address);
+        let group = groups.iter_mut().find(|group| {
+            group[0] == address || (peer_id.is_some() && peer_id_of(&group[0]) == peer_id)
+        });
+        match group {
+            Some(group) if !group.contains(&address) => group.push(address),
+            Some(_) => {}
+            None => groups.push(vec![address]),
+        }
+    }
+    groups
+}
+
+/// Builds the options for dialing a peer at `addresses`, a group from `group_by_peer`. If they end
+/// with a `/p2p/` component, the dial fails unless the remote turns out to have that PeerId.
+pub fn dial_opts(mut addresses: Vec<Multiaddr>) -> DialOpts {
+    match addresses.first().and_then(peer_id_of) {
+        Some(peer_id) => {
+            for address in &mut addresses {
+                address.pop();
+            }
+            DialOpts::peer_id(peer_id).addresses(addresses).build()
+        }
+        None => DialOpts::unknown_peer_id().address(addresses.swap_remove(0)).build(),
+    }
+}
+
+/// What the sending side sends once it's connected: `requests` copies of `request`, with at most
+/// `concurrency` of them in flight at once.
+#[derive(Clone)]
+pub struct Workload {
+    pub request: Request,
+    pub requests: u64,
+    pub concurrency: u64,
+}
+
+impl Workload {
+    pub fn single(request: Request) -> Self {
+        Self { request, requests: 1, concurrency: 1 }
+    }
+}
+
+/// The progress of the sending side through its workload with one peer.
+#[derive(Default)]
+struct PeerProgress {
+    in_flight: HashSet<OutboundRequestId>,
+    sent: u64,
+    completed: u64,
+    /// Set once it's known whether the bug occurred with this peer.
+    verdict: Option<Verdict>,
+}
+
+/// The progress of the sending side through its workload, which it sends to every peer it dials.
+pub struct Sender {
+    workload: Workload,
+    expected_peers: usize,
+    /// Whether the workload is also sent to peers that dial this node.
+    inbound_peers: bool,
+    peers: HashMap<PeerId, PeerProgress>,
+    /// Addresses of the dials that don't know the PeerId of their peer.
+    dials: HashMap<ConnectionId, Multiaddr>,
+    /// Outcomes with the peers whose dial failed before their PeerId was known.
+    unreachable: Vec<PeerVerdict>,
+    stats: TransferStats,
+    progress: Option<watch::Receiver<Progress>>,
+    progress_interval: Option<Duration>,
+}
+
+impl Sender {
+    pub fn new(workload: Workload) -> Self {
+        Self {
+            workload,
+            expected_peers: 1,
+            inbound_peers: false,
+            peers: HashMap::new(),
+            dials: HashMap::new(),
+            unreachable: Vec::new(),
+            stats: TransferStats::default(),
+            progress: None,
+            progress_interval: None,
+        }
+    }
+
+    /// Waits for the outcome with `expected_peers` peers instead of one before returning a
+    /// verdict.
+    pub fn with_expected_peers(mut self, expected_peers: usize) -> Self {
+        self.expected_peers = expected_peers;
+        self
+    }
+
+    /// Also sends the workload to the first peers that dial this node, up to the expected amount.
+    /// For when this node doesn't dial any peer itself.
+    pub fn with_inbound_peers(mut self) -> Self {
+        self.inbound_peers = true;
+        self
+    }
+
+    /// Logs the progress of the response being read every `interval`. `progress` should be
+    /// subscribed to the channel given to `Codec::with_progress`.
+    pub fn with_progress(
+        mut self,
+        progress: watch::Receiver<Progress>,
+        interval: Duration,
+    ) -> Self {
+        self.progress = Some(progress);
+        self.progress_interval = Some(interval);
+        self
+    }
+
+    /// Dials a peer at `addresses`, a group from `group_by_peer`, to send the workload to it.
+    pub fn dial(
+        &mut self,
+        swarm: &mut Swarm<Behaviour>,
+        addresses: Vec<Multiaddr>,
+    ) -> Result<(), DialError> {
+        let address = addresses[0].clone();
+        let opts = dial_opts(addresses);
+        let connection_id = opts.connection_id();
+        let peer_id = opts.get_peer_id();
+        swarm.dial(opts)?;
+        if peer_id.is_none() {
+            self.dials.insert(connection_id, address);
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> &TransferStats {
+        &self.stats
+    }
+
+    /// The verdict of a run that timed out. Peers that already have an outcome keep it.
+    pub fn timeout_verdict(&mut self) -> Verdict {
+        for peer in self.peers.values_mut() {
+            peer.verdict.get_or_insert(Verdict::Timeout);
+        }
+        self.verdict().unwrap_or(Verdict::Timeout)
+    }
+
+    /// Starts sending the workload over a new connection with `peer_id`, unless it's a peer that
+    /// dialed this node and isn't expected.
+    fn on_established(
+        &mut self,
+        behaviour: &mut request_response::Behaviour<Codec>,
+        peer_id: &PeerId,
+        dialed: bool,
+    ) {
+        let expected = self.peers.contains_key(peer_id)
+            || dialed
+            || (self.inbound_peers && self.peers.len() < self.expected_peers);
+        if !expected {
+            info!(%peer_id, "Not sending requests to an unexpected peer that dialed this node");
+            return;
+        }
+        self.fill_in_flight(behaviour, peer_id);
+    }
+
+    /// Sends requests to `peer_id` until `concurrency` of them are in flight or all of them were
+    /// sent.
+    fn fill_in_flight(
+        &mut self,
+        behaviour: &mut request_response::Behaviour<Codec>,
+        peer_id: &PeerId,
+    ) {
+        let peer = self.peers.entry(*peer_id).or_default();
+        while peer.verdict.is_none()
+            && (peer.in_flight.len() as u64) < self.workload.concurrency
+            && peer.sent < self.workload.requests
+        {
+            let request_id = behaviour.send_request(peer_id, self.workload.request.clone());
+            peer.in_flight.insert(request_id);
+            self.stats.on_sent(request_id);
+            peer.sent += 1;
+        }
+    }
+
+    fn on_response(
+        &mut self,
+        peer_id: &PeerId,
+        request_id: &OutboundRequestId,
+        response: &Response,
+    ) {
+        let Some(peer) = self.peers.get_mut(peer_id) else {
+            return;
+        };
+        if peer.in_flight.remove(request_id) {
+            peer.completed += 1;
+            self.stats.on_response(request_id, response.arrival);
+            if peer.completed == self.workload.requests {
+                peer.verdict.get_or_insert(Verdict::ResponseReceived);
+            }
+        }
+    }
+
+    /// Logs the progress of the response being read. If `only_if_changed`, logs nothing unless
+    /// bytes arrived since the last time.
+    fn log_progress(&mut self, only_if_changed: bool) {
+        let Some(progress) = &mut self.progress else {
+            return;
+        };
+        if only_if_changed && !progress.has_changed().unwrap_or(false) {
+            return;
+        }
+        let Progress { bytes, elapsed } = *progress.borrow_and_update();
+        info!(
+            bytes,
+            elapsed_millis = elapsed.as_millis() as u64,
+            completed = self.peers.values().map(|peer| peer.completed).sum::<u64>(),
+            requested = self.workload.requests * self.expected_peers as u64,
+            "Reading response"
+        );
+    }
+
+    /// Records the outcome with `peer_id`, unless it already has one.
+    fn conclude(&mut self, peer_id: PeerId, verdict: impl FnOnce(&PeerProgress) -> Verdict) {
+        let peer = self.peers.entry(peer_id).or_default();
+        if peer.verdict.is_none() {
+            peer.verdict = Some(verdict(peer));
+        }
+    }
+
+    /// Records that the last connection with `peer_id` was closed.
+    fn on_closed(&mut self, peer_id: PeerId, cause: Option<&ConnectionError>) {
+        if !self.peers.contains_key(&peer_id) {
+            return;
+        }
+        let requested = self.workload.requests;
+        self.conclude(peer_id, |peer| Verdict::ClosedBeforeResponse {
+            completed: peer.completed,
+            requested,
+            cause: describe_close_cause(cause),
+        });
+    }
+
+    fn on_failure(&mut self, peer_id: PeerId, error: &OutboundFailure) {
+        if !self.peers.contains_key(&peer_id) {
+            return;
+        }
+        let requested = self.workload.requests;
+        self.conclude(peer_id, |peer| match error {
+            OutboundFailure::ConnectionClosed => Verdict::ClosedBeforeResponse {
+                completed: peer.completed,
+                requested,
+                cause: "closed while the request was in flight".to_owned(),
+            },
+            OutboundFailure::Timeout => Verdict::RequestTimeout,
+            OutboundFailure::DialFailure => Verdict::DialFailure {
+                error: "the request-response behaviour failed to dial the peer".to_owned(),
+            },
+            OutboundFailure::UnsupportedProtocols => Verdict::UnsupportedProtocols,
+            OutboundFailure::Io(error) => Verdict::StreamIo { error: error.to_string() },
+        });
+    }
+
+    /// Records the failure of the dial `connection_id`, which never connected to a peer.
+    fn on_dial_failure(
+        &mut self,
+        connection_id: ConnectionId,
+        peer_id: Option<PeerId>,
+        error: &DialError,
+    ) {
+        let verdict = Verdict::DialFailure { error: error.to_string() };
+        match peer_id {
+            Some(peer_id) => self.conclude(peer_id, |_| verdict),
+            None => {
+                if let Some(address) = self.dials.remove(&connection_id) {
+                    self.unreachable.push(PeerVerdict { peer: address.to_string(), verdict });
+                }
+            }
+        }
+    }
+
+    /// The verdict of the run, once every expected peer has an outcome. With a single peer, that's
+    /// its outcome.
+    fn verdict(&mut self) -> Option<Verdict> {
+        let concluded = self.peers.values().filter(|peer| peer.verdict.is_some()).count()
+            + self.unreachable.len();
+        if concluded < self.expected_peers || concluded == 0 {
+            return None;
+        }
+        let mut peers: Vec<PeerVerdict> = self
+            .peers
+            .iter_mut()
+            .filter_map(|(peer, progress)| {
+                progress
+                    .verdict
+                    .take()
+                    .map(|verdict| PeerVerdict { peer: peer.to_string(), verdict })
+            })
+            .chain(self.unreachable.drain(..))
+            .collect();
+        if self.expected_peers == 1 && peers.len() == 1 {
+            return peers.pop().map(|peer| peer.verdict);
+        }
+        peers.sort_by(|a, b| a.peer.cmp(&b.peer));
+        Some(Verdict::PerPeer { peers })
+    }
+}
+
+/// Describes why the receiving side failed to answer a request.
+fn describe_inbound_failure(error: &InboundFailure) -> String {
+    match error {
+        InboundFailure::ConnectionClosed => "the connection was closed while responding".to_owned(),
+        InboundFailure::Timeout => "the response wasn't sent within the request timeout".to_owned(),
+        InboundFailure::UnsupportedProtocols => {
+            "the remote requested an unsupported protocol".to_owned()
+        }
+        InboundFailure::ResponseOmission => {
+            "the response was dropped without being sent".to_owned()
+        }
+        InboundFailure::Io(error) => format!("an error occurred on the stream: {error}"),
+    }
+}
+
+/// When a node stops besides the sending side knowing whether the bug occurred. A node also stops
+/// on Ctrl-C.
+#[derive(Default)]
+pub struct StopConditions {
+    pub max_duration: Option<Duration>,
+    /// Stop once this many responses were sent.
+    pub exit_after_responses: Option<u64>,
+    /// Stop once a value is sent through this channel, like on Ctrl-C.
+    pub shutdown: Option<oneshot::Receiver<()>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum StopReason {
+    MaxDuration,
+    ResponseLimit,
+    Interrupted,
+    Shutdown,
+}
+
+/// The responses the receiving side handed to the behaviour.
+#[derive(Default)]
+struct Responder {
+    /// Requests whose response was handed to the behaviour but neither sent nor failed yet.
+    pending: HashSet<InboundRequestId>,
+    sent: u64,
+    failures: u64,
+}
+
+enum Next {
+    Event(Box<SwarmEvent<BehaviourEvent>>),
+    Stop(StopReason),
+    LogProgress,
+    CloseTimeout,
+}
+
+/// Waits until `deadline`, or forever if there's none.
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => future::pending().await,
+    }
+}
+
+/// Waits for the next tick of `interval`, or forever if there's none.
+async fn tick(interval: Option<&mut Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+/// How long a node that is done waits for its connections to close before returning. A receiving
+/// side that is stopping waits that long for its remotes to close them, and then closes them
+/// itself.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a node waits after its connections were closed before returning. QUIC reports a
+/// connection closed before it sent the close to the remote, from a task of its own.
+const CLOSE_GRACE_PERIOD: Duration = Duration::from_millis(100);
+
+/// Closes the connections of `swarm`, and drives it until they're closed or `CLOSE_TIMEOUT` passed.
+/// Otherwise the remotes may not learn that their last responses arrived before the process exits,
+/// and wait for them until their request timeout.
+async fn close_connections(swarm: &mut Swarm<Behaviour>) {
+    let peers: Vec<PeerId> = swarm.connected_peers().copied().collect();
+    for peer in peers {
+        let _ = swarm.disconnect_peer_id(peer);
+    }
+    let closed = async {
+        while swarm.network_info().num_peers() > 0 {
+            log_swarm_event(&swarm.select_next_some().await);
+        }
+    };
+    if tokio::time::timeout(CLOSE_TIMEOUT, closed).await.is_err() {
+        warn!("Returning before all the connections were closed");
+    }
+    tokio::time::sleep(CLOSE_GRACE_PERIOD).await;
+}
+
+/// Makes a node that is stopping for `reason` deny new connections while its pending responses
+/// drain.
+fn stop_accepting(
+    swarm: &mut Swarm<Behaviour>,
+    reason: StopReason,
+    responder: &Responder,
+) -> StopReason {
+    info!(
+        ?reason,
+        responses_sent = responder.sent,
+        pending_responses = responder.pending.len(),
+        "Stopping"
+    );
+    swarm.behaviour_mut().admission.close();
+    reason
+}
+
+/// Runs the event loop of a node. The sending side is the one given a `sender`, and returns once
+/// it knows whether the bug occurred with every peer it expects and it closed its connections.
+/// Otherwise the node returns if it dialed a peer with an unexpected PeerId, or once one of the
+/// `stop` conditions is met.
+///
+/// On stopping, the node denies new connections and waits until its pending responses were sent or
+/// failed. The receiving side then waits for its remotes to close the connections, since they may
+/// still be reading the last responses. Only then does the node close its connections and
+/// listeners and return.
+pub async fn run_node(
+    swarm: &mut Swarm<Behaviour>,
+    mut sender: Option<&mut Sender>,
+    stop: StopConditions,
+) -> Verdict {
+    let StopConditions { max_duration, exit_after_responses, shutdown } = stop;
+    let max_duration = async move {
+        match max_duration {
+            Some(max_duration) => tokio::time::sleep(max_duration).await,
+            None => future::pending().await,
+        }
+    };
+    let shutdown = async move {
+        match shutdown {
+            // Dropping the sending half doesn't stop the node.
+            Some(shutdown) => {
+                if shutdown.await.is_err() {
+                    future::pending::<()>().await
+                }
+            }
+            None => future::pending().await,
+        }
+    };
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(max_duration, shutdown, ctrl_c);
+
+    let mut responder = Responder::default();
+    let mut listeners = HashSet::<ListenerId>::new();
+    let mut stop_reason = None;
+    let mut close_deadline = None;
+    let mut progress_interval =
+        sender.as_ref().and_then(|sender| sender.progress_interval).map(tokio::time::interval);
+    loop {
+        if let Some(reason) = stop_reason {
+            if responder.pending.is_empty() {
+                let close_deadline =
+                    *close_deadline.get_or_insert_with(|| Instant::now() + CLOSE_TIMEOUT);
+                let closed_by_remotes = swarm.network_info().num_peers() == 0;
+                if sender.is_some() || closed_by_remotes || Instant::now() >= close_deadline {
+                    close_connections(swarm).await;
+                    for listener_id in listeners.drain() {
+                        swarm.remove_listener(listener_id);
+                    }
+                    return match (&sender, reason) {
+                        (Some(_), StopReason::MaxDuration) => Verdict::Timeout,
+                        (Some(_), _) => Verdict::Interrupted,
+                        (None, _) => Verdict::Served {
+                            responses_sent: responder.sent,
+                            inbound_failures: responder.failures,
+                        },
+                    };
+                }
+            }
+        }
+
+        let next = tokio::select! {
+            event = swarm.select_next_some() => Next::Event(Box::new(event)),
+            _ = &mut max_duration, if stop_reason.is_none() => Next::Stop(StopReason::MaxDuration),
+            _ = &mut ctrl_c, if stop_reason.is_none() => Next::Stop(StopReason::Interrupted),
+            _ = &mut shutdown, if stop_reason.is_none() => Next::Stop(StopReason::Shutdown),
+            _ = tick(progress_interval.as_mut()) => Next::LogProgress,
+            _ = sleep_until(close_deadline) => Next::CloseTimeout,
+        };
+        let event = match next {
+            Next::Event(event) => *event,
+            Next::Stop(reason) => {
+                stop_reason = Some(stop_accepting(swarm, reason, &responder));
+                continue;
+            }
+            Next::LogProgress => {
+                if let Some(sender) = &mut sender {
+                    sender.log_progress(true);
+                }
+                continue;
+            }
+            Next::CloseTimeout => continue,
+        };
+        log_swarm_event(&event);
+        match event {
+            SwarmEvent::NewListenAddr { listener_id, .. } => {
+                listeners.insert(listener_id);
+            }
+            SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                if let Some(sender) = &mut sender {
+                    sender.on_established(
+                        &mut swarm.behaviour_mut().request_response,
+                        &peer_id,
+                        endpoint.is_dialer(),
+                    );
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
+                request_response::Event::Message {
+                    message: request_response::Message::Request { request_id, request, channel },
+                    ..
+                },
+            )) => {
+                let response = Response::new(request.response_size_in_kilobyte);
+                match swarm.behaviour_mut().request_response.send_response(channel, response) {
+                    Ok(()) => {
+                        responder.pending.insert(request_id);
+                    }
+                    Err(_) => {
+                        warn!("Failed to respond: the request was already closed or timed out")
+                    }
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
+                request_response::Event::ResponseSent { request_id, .. },
+            )) => {
+                responder.pending.remove(&request_id);
+                responder.sent += 1;
+                if stop_reason.is_none()
+                    && matches!(exit_after_responses, Some(limit) if responder.sent >= limit)
+                {
+                    stop_reason =
+                        Some(stop_accepting(swarm, StopReason::ResponseLimit, &responder));
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
+                request_response::Event::Message {
+                    peer,
+                    message: request_response::Message::Response { request_id, response },
+                },
+            )) => {
+                if let Some(sender) = &mut sender {
+                    sender.on_response(&peer, &request_id, &response);
+                    sender.fill_in_flight(&mut swarm.behaviour_mut().request_response, &peer);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
+                request_response::Event::OutboundFailure { peer, error, .. },
+            )) => {
+                if let Some(sender) = &mut sender {
+                    sender.on_failure(peer, &error);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
+                request_response::Event::InboundFailure { peer, request_id, error },
+            )) => {
+                responder.pending.remove(&request_id);
+                responder.failures += 1;
+                warn!(%peer, "Failed to respond: {}", describe_inbound_failure(&error));
+            }
+            SwarmEvent::OutgoingConnectionError {
+                peer_id: Some(expected),
+                error: DialError::WrongPeerId { obtained, .. },
+                ..
+            } => match &mut sender {
+                Some(sender) => {
+                    sender.conclude(expected, |_| Verdict::WrongPeerId { expected, obtained })
+                }
+                None => return Verdict::WrongPeerId { expected, obtained },
+            },
+            SwarmEvent::OutgoingConnectionError { connection_id, peer_id, error } => {
+                // Another connection may have reached the peer meanwhile.
+                if let Some(sender) = sender
+                    .as_mut()
+                    .filter(|_| !peer_id.is_some_and(|peer_id| swarm.is_connected(&peer_id)))
+                {
+                    sender.on_dial_failure(connection_id, peer_id, &error);
+                }
+            }
+            SwarmEvent::ConnectionClosed { peer_id, cause, num_established: 0, .. } => {
+                if let Some(sender) = &mut sender {
+                    sender.log_progress(false);
+                    sender.on_closed(peer_id, cause.as_ref());
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(verdict) = sender.as_mut().and_then(|sender| sender.verdict()) {
+            close_connections(swarm).await;
+            return verdict;
+        }
+    }
+}
+
+/// Spawns a task running a receiving swarm that listens on an in-memory address until `stop`, and
+/// returns that address along with the task.
+pub async fn spawn_receiver(
+    codec: Codec,
+    idle_connection_timeout: Duration,
+    mitigations: Mitigations,
+    stop: StopConditions,
+) -> (Multiaddr, JoinHandle<Verdict>) {
+    let mut receiver = build_swarm(
+        Transport::Memory,
+        Keypair::generate_ed25519(),
+        codec.clone(),
+        idle_connection_timeout,
+        mitigations,
+    );
+    receiver
+        .listen_on(Multiaddr::empty().with(Protocol::Memory(0)))
+        .expect("Error while binding to a memory address");
+    let listen_address = loop {
+        if let SwarmEvent::NewListenAddr { address, .. } = receiver.select_next_some().await {
+            break address;
+        }
+    };
+    let receiver_task = tokio::spawn(
+        async move { run_node(&mut receiver, None, stop).await }
+            .instrument(info_span!("node", role = "receiver", %listen_address)),
+    );
+    (listen_address, receiver_task)
+}
+
+/// Runs receiving swarms and a sending swarm in this process, connected over an in-memory
+/// transport, until the sending side knows whether the bug occurred. There's a receiving swarm for
+/// every peer the sender expects, and all the swarms are built with `mitigations`.
+pub async fn run_in_process(
+    codec: Codec,
+    sender: &mut Sender,
+    idle_connection_timeout: Duration,
+    mitigations: Mitigations,
+) -> Verdict {
+    let mut listen_addresses = Vec::new();
+    let mut receiver_tasks = Vec::new();
+    for _ in 0..sender.expected_peers {
+        let (listen_address, receiver_task) = spawn_receiver(
+            codec.clone(),
+            idle_connection_timeout,
+            mitigations,
+            StopConditions::default(),
+        )
+        .await;
+        listen_addresses.push(listen_address);
+        receiver_tasks.push(receiver_task);
+    }
+
+    let mut sender_swarm = build_swarm(
+        Transport::Memory,
+        Keypair::generate_ed25519(),
+        codec,
+        idle_connection_timeout,
+        mitigations,
+    );
+    for listen_address in listen_addresses {
+        sender
+            .dial(&mut sender_swarm, vec![listen_address.clone()])
+            .unwrap_or_else(|error| panic!("Error while dialing {}: {:?}", listen_address, error));
+    }
+    let verdict = run_node(&mut sender_swarm, Some(sender), StopConditions::default())
+        .instrument(info_span!("node", role = "sender"))
+        .await;
+
+    for receiver_task in receiver_tasks {
+        receiver_task.abort();
+    }
+    verdict
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,335 @@
+//! Runs a dialing and a listening swarm over the memory transport on a current-thread runtime
+//! whose time is paused, so that a throttled `write_response` moves on only when the test lets
+//! time pass. Everything the swarms do happens at an exact point in time, and so does the order of
+//! their events.
+//!
+//! A nonzero idle connection timeout runs on `futures-timer` rather than on the time of tokio, so
+//! it can't be advanced, and neither can the timers of identify and ping. A zero idle connection
+//! timeout closes a connection as soon as it has no active stream and no handler keeps it alive,
+//! without a timer, so the tests that pin the idle close use that.
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use libp2p::identity::Keypair;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::swarm::{ConnectionError, SwarmEvent};
+use libp2p::{request_response, Multiaddr, PeerId, Swarm};
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+use super::{build_swarm, group_by_peer, Transport};
+use crate::behaviour::{Behaviour, BehaviourEvent};
+use crate::codec::{Codec, Progress, Request, Response, Throttle};
+use crate::mitigation::{Mitigation, Mitigations};
+
+/// An idle connection timeout long enough never to fire during a test.
+const LONG_IDLE_CONNECTION_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Time after which a test gives up waiting, if the swarms are stuck.
+const GIVE_UP_AFTER: Duration = Duration::from_secs(600);
+
+/// The kinds of events the tests follow. Listen addresses, dialing, identify and ping are left
+/// out.
+fn kind(event: &SwarmEvent<BehaviourEvent>) -> Option<&'static str> {
+    match event {
+        SwarmEvent::ConnectionEstablished { .. } => Some("connection_established"),
+        SwarmEvent::ConnectionClosed { cause: Some(ConnectionError::KeepAliveTimeout), .. } => {
+            Some("idle_close")
+        }
+        SwarmEvent::ConnectionClosed { .. } => Some("connection_closed"),
+        SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(event)) => Some(match event {
+            request_response::Event::Message {
+                message: request_response::Message::Request { .. },
+                ..
+            } => "request",
+            request_response::Event::Message {
+                message: request_response::Message::Response { .. },
+                ..
+            } => "response",
+            request_response::Event::OutboundFailure { .. } => "outbound_failure",
+            request_response::Event::InboundFailure { .. } => "inbound_failure",
+            request_response::Event::ResponseSent { .. } => "response_sent",
+        }),
+        _ => None,
+    }
+}
+
+struct Harness {
+    dialer: Swarm<Behaviour>,
+    listener: Swarm<Behaviour>,
+    listener_peer: PeerId,
+    dialer_events: Vec<&'static str>,
+    listener_events: Vec<&'static str>,
+}
+
+impl Harness {
+    /// Connects a dialing swarm using `dialer_codec` to a listening swarm using `listener_codec`.
+    /// Both are built with `idle_connection_timeout` and `mitigations`.
+    async fn connect(
+        dialer_codec: Codec,
+        listener_codec: Codec,
+        idle_connection_timeout: Duration,
+        mitigations: Mitigations,
+    ) -> Self {
+        let mut listener = build_swarm(
+            Transport::Memory,
+            Keypair::generate_ed25519(),
+            listener_codec,
+            idle_connection_timeout,
+            mitigations,
+        );
+        listener
+            .listen_on(Multiaddr::empty().with(Protocol::Memory(0)))
+            .expect("Error while binding to a memory address");
+        let listen_address = loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = listener.select_next_some().await {
+                break address;
+            }
+        };
+        let mut dialer = build_swarm(
+            Transport::Memory,
+            Keypair::generate_ed25519(),
+            dialer_codec,
+            idle_connection_timeout,
+            mitigations,
+        );
+        dialer
+            .dial(DialOpts::unknown_peer_id().address(listen_address.clone()).build())
+            .unwrap_or_else(|error| panic!("Error while dialing {}: {:?}", listen_address, error));
+
+        let mut harness = Self {
+            listener_peer: *listener.local_peer_id(),
+            dialer,
+            listener,
+            dialer_events: Vec::new(),
+            listener_events: Vec::new(),
+        };
+        harness
+            .settle(|harness| {
+                harness.dialer_events.contains(&"connection_established")
+                    && harness.listener_events.contains(&"connection_established")
+            })
+            .await;
+        harness
+    }
+
+    /// Drives both swarms until `deadline`, or until `done` holds. The listening side answers
+    /// every request with a response of the requested size.
+    async fn run_until(&mut self, deadline: Instant, done: impl Fn(&Self) -> bool) {
+        while !done(self) {
+            tokio::select! {
+                biased;
+                event = self.dialer.select_next_some() => {
+                    self.dialer_events.extend(kind(&event));
+                }
+                event = self.listener.select_next_some() => {
+                    self.listener_events.extend(kind(&event));
+                    if let SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
+                        request_response::Event::Message {
+                            message: request_response::Message::Request { request, channel, .. },
+                            ..
+                        },
+                    )) = event
+                    {
+                        let response = Response::new(request.response_size_in_kilobyte);
+                        self.listener
+                            .behaviour_mut()
+                            .request_response
+                            .send_response(channel, response)
+                            .expect("Error while sending the response");
+                    }
+                }
+                _ = tokio::time::sleep_until(deadline) => return,
+            }
+        }
+    }
+
+    /// Lets `duration` pass, driving both swarms meanwhile.
+    async fn advance(&mut self, duration: Duration) {
+        self.run_until(Instant::now() + duration, |_| false).await;
+    }
+
+    /// Drives both swarms until `done` holds, however long it takes.
+    async fn settle(&mut self, done: impl Fn(&Self) -> bool) {
+        self.run_until(Instant::now() + GIVE_UP_AFTER, &done).await;
+        assert!(
+            done(self),
+            "Stuck with dialer events {:?} and listener events {:?}",
+            self.dialer_events,
+            self.listener_events
+        );
+    }
+
+    fn send_request(&mut self, response_size_in_kilobyte: u64) {
+        let listener_peer = self.listener_peer;
+        self.dialer
+            .behaviour_mut()
+            .request_response
+            .send_request(&listener_peer, Request::new(0, response_size_in_kilobyte));
+    }
+}
+
+#[tokio::test(flavor = "current_thread", start_paused = true)]
+async fn response_is_delivered() {
+    let mut harness = Harness::connect(
+        Codec::new(4),
+        Codec::new(4),
+        LONG_IDLE_CONNECTION_TIMEOUT,
+        Mitigations::default(),
+    )
+    .await;
+    harness.send_request(4);
+    harness
+        .settle(|harness| {
+            harness.dialer_events.contains(&"response")
+                && harness.listener_events.contains(&"response_sent")
+        })
+        .await;
+
+    assert_eq!(harness.dialer_events, ["connection_established", "response"]);
+    assert_eq!(harness.listener_events, ["connection_established", "request", "response_sent"]);
+}
+
+#[tokio::test(flavor = "current_thread", start_paused = true)]
+async fn throttled_response_arrives_one_chunk_per_delay() {
+    let (progress_sender, progress) = watch::channel(Progress::default());
+    let mut harness = Harness::connect(
+        Codec::new(4).with_progress(progress_sender),
+        Codec::new(4).with_write_throttle(Throttle::ChunkDelay(Duration::from_secs(1))),
+        LONG_IDLE_CONNECTION_TIMEOUT,
+        Mitigations::default(),
+    )
+    .await;
+    harness.send_request(4);
+
+    // Every 1KB chunk is written one second after the previous one, starting a second after the
+    // request arrived. Checking half way between chunks keeps clear of the points they're written
+    // at.
+    harness.advance(Duration::from_millis(500)).await;
+    assert_eq!(progress.borrow().bytes, 0);
+    for chunks in 1..=3 {
+        harness.advance(Duration::from_secs(1)).await;
+        assert_eq!(progress.borrow().bytes, chunks * 1024);
+        assert_eq!(harness.dialer_events, ["connection_established"]);
+    }
+    harness.advance(Duration::from_secs(1)).await;
+    harness.settle(|harness| harness.dialer_events.contains(&"response")).await;
+
+    assert_eq!(progress.borrow().bytes, 4 * 1024);
+    assert_eq!(harness.dialer_events, ["connection_established", "response"]);
+}
+
+#[tokio::test(flavor = "current_thread", start_paused = true)]
+async fn disconnecting_during_write_response_fails_the_request() {
+    let (progress_sender, progress) = watch::channel(Progress::default());
+    let mut harness = Harness::connect(
+        Codec::new(4).with_progress(progress_sender),
+        Codec::new(4).with_write_throttle(Throttle::ChunkDelay(Duration::from_secs(1))),
+        LONG_IDLE_CONNECTION_TIMEOUT,
+        Mitigations::default(),
+    )
+    .await;
+    harness.send_request(4);
+
+    // Half way between the first and the second chunk.
+    harness.advance(Duration::from_millis(1500)).await;
+    assert_eq!(progress.borrow().bytes, 1024);
+    let listener_peer = harness.listener_peer;
+    harness.dialer.disconnect_peer_id(listener_peer).expect("Not connected");
+    harness
+        .settle(|harness| {
+            harness.dialer_events.contains(&"outbound_failure")
+                && harness.listener_events.contains(&"connection_closed")
+        })
+        .await;
+
+    assert_eq!(
+        harness.dialer_events,
+        ["connection_established", "connection_closed", "outbound_failure"]
+    );
+    assert!(!harness.listener_events.contains(&"response_sent"));
+    assert_eq!(harness.listener_events[..2], ["connection_established", "request"]);
+}
+
+/// Connects with a zero idle connection timeout and `mitigations`, sends a request for 4KB that is
+/// written 1KB a second, and checks that the connection stays open while the response is written.
+async fn write_slow_response_with_zero_idle_timeout(
+    mitigations: Mitigations,
+) -> (Harness, watch::Receiver<Progress>) {
+    let (progress_sender, progress) = watch::channel(Progress::default());
+    let mut harness = Harness::connect(
+        Codec::new(4).with_progress(progress_sender),
+        Codec::new(4).with_write_throttle(Throttle::ChunkDelay(Duration::from_secs(1))),
+        Duration::ZERO,
+        mitigations,
+    )
+    .await;
+    harness.send_request(4);
+
+    // The stream of the response is active while it's written, so the connection isn't idle.
+    harness.advance(Duration::from_millis(500)).await;
+    for chunks in 0..=3 {
+        assert_eq!(progress.borrow().bytes, chunks * 1024);
+        assert_eq!(harness.dialer_events, ["connection_established"]);
+        assert_eq!(harness.listener_events, ["connection_established", "request"]);
+        harness.advance(Duration::from_secs(1)).await;
+    }
+    (harness, progress)
+}
+
+#[tokio::test(flavor = "current_thread", start_paused = true)]
+async fn zero_idle_timeout_closes_before_the_last_chunk_is_read() {
+    let (harness, progress) =
+        write_slow_response_with_zero_idle_timeout(Mitigations::default()).await;
+
+    // Once the last chunk is written, the receiving side has no active stream left and closes the
+    // connection before the sending side reads that chunk.
+    assert_eq!(progress.borrow().bytes, 3 * 1024);
+    assert_eq!(
+        harness.dialer_events,
+        ["connection_established", "connection_closed", "outbound_failure"]
+    );
+    assert_eq!(
+        harness.listener_events,
+        ["connection_established", "request", "response_sent", "idle_close"]
+    );
+}
+
+#[tokio::test(flavor = "current_thread", start_paused = true)]
+async fn keep_alive_mitigation_delivers_the_response_with_zero_idle_timeout() {
+    let (mut harness, progress) = write_slow_response_with_zero_idle_timeout(Mitigations::new(
+        &[Mitigation::KeepAlive],
+        Duration::from_secs(60),
+    ))
+    .await;
+    harness
+        .settle(|harness| {
+            harness.dialer_events.contains(&"idle_close")
+                && harness.listener_events.contains(&"idle_close")
+        })
+        .await;
+
+    // The sending side reads the whole response, and only then do both sides close the idle
+    // connection.
+    assert_eq!(progress.borrow().bytes, 4 * 1024);
+    assert_eq!(harness.dialer_events, ["connection_established", "response", "idle_close"]);
+    assert_eq!(
+        harness.listener_events,
+        ["connection_established", "request", "response_sent", "idle_close"]
+    );
+}
+
+#[test]
+fn addresses_of_the_same_peer_are_grouped() {
+    let peer_id = PeerId::random();
+    let address = |address: &str| address.parse::<Multiaddr>().expect("Invalid address");
+    let quic = address("/ip4/127.0.0.1/udp/4001/quic-v1").with(Protocol::P2p(peer_id));
+    let tcp = address("/ip4/127.0.0.1/tcp/4001").with(Protocol::P2p(peer_id));
+    let unknown = address("/ip4/127.0.0.1/tcp/4002");
+    assert_eq!(
+        group_by_peer(vec![quic.clone(), unknown.clone(), tcp.clone(), unknown.clone()]),
+        [vec![quic, tcp], vec![unknown]]
+    );
+}
@@ -0,0 +1,224 @@
+use std::cmp::Reverse;
+use std::collections::binary_heap::PeekMut;
+use std::collections::{BinaryHeap, HashMap};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::info;
+
+#[derive(clap::Args)]
+pub struct ProxyArgs {
+    /// UDP address the proxy receives the packets of the dialing side on.
+    #[arg(short, long)]
+    listen_address: SocketAddr,
+
+    /// UDP address of the listening side, which the packets are forwarded to.
+    #[arg(short, long)]
+    upstream_address: SocketAddr,
+
+    #[command(flatten)]
+    impairment: Impairment,
+}
+
+/// What happens to every packet on its way, in each direction.
+#[derive(Clone, Copy, clap::Args)]
+pub struct Impairment {
+    /// Amount of time every packet is delayed by.
+    #[arg(long, default_value_t = 0)]
+    delay_millis: u64,
+
+    /// Maximal amount of time every packet is delayed by on top of the delay, picked uniformly. A
+    /// packet is never delivered before the one ahead of it, unless that one is held back.
+    #[arg(long, default_value_t = 0)]
+    jitter_millis: u64,
+
+    /// Probability of dropping a packet.
+    #[arg(long, default_value_t = 0.0, value_parser = parse_probability)]
+    loss: f64,
+
+    /// Probability of holding a packet back, so that the ones after it overtake it.
+    #[arg(long, default_value_t = 0.0, value_parser = parse_probability)]
+    reorder: f64,
+
+    /// Amount of time a packet that's held back is delayed by on top of the others.
+    #[arg(long, default_value_t = 20)]
+    reorder_delay_millis: u64,
+
+    /// Probability of delivering a packet twice.
+    #[arg(long, default_value_t = 0.0, value_parser = parse_probability)]
+    duplicate: f64,
+
+    /// If given, packets are queued so that no more than this amount of 1KB is sent per second.
+    #[arg(long)]
+    bandwidth_kilobyte_per_sec: Option<u64>,
+}
+
+fn parse_probability(s: &str) -> Result<f64, String> {
+    let probability: f64 = s.parse().map_err(|_| format!("Invalid probability {s}"))?;
+    if !(0.0..=1.0).contains(&probability) {
+        return Err(format!("Probability {s} isn't between 0 and 1"));
+    }
+    Ok(probability)
+}
+
+/// A packet on its way, ordered by when it arrives and then by when it was sent.
+type Scheduled = Reverse<(Instant, u64, Vec<u8>)>;
+
+/// One direction of the proxy for one dialing side.
+struct Link {
+    impairment: Impairment,
+    rng: StdRng,
+    /// When the packets queued so far finish being sent, if there's a bandwidth cap.
+    free_at: Instant,
+    /// When the last packet that wasn't held back arrives. The next ones don't overtake it.
+    last_arrival: Instant,
+    /// Hands the packets over to the task sending them, see `forward`.
+    packets: mpsc::UnboundedSender<(Instant, Vec<u8>)>,
+}
+
+impl Link {
+    /// Creates a link whose packets are sent from `socket` to `target`.
+    fn new(impairment: Impairment, socket: Arc<UdpSocket>, target: SocketAddr) -> Self {
+        let (packets, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(forward(socket, target, receiver));
+        let now = Instant::now();
+        Self { impairment, rng: StdRng::from_entropy(), free_at: now, last_arrival: now, packets }
+    }
+
+    /// Impairs `packet` and hands its copies over to be sent when they arrive.
+    fn send(&mut self, packet: &[u8]) {
+        for arrival in self.schedule(packet.len()) {
+            // The task sending the packets runs as long as the proxy.
+            let _ = self.packets.send((arrival, packet.to_vec()));
+        }
+    }
+
+    /// The times at which copies of a packet of `length` bytes arrive. Empty if it's lost.
+    fn schedule(&mut self, length: usize) -> Vec<Instant> {
+        let impairment = self.impairment;
+        if self.rng.gen_bool(impairment.loss) {
+            return Vec::new();
+        }
+        let now = Instant::now();
+        let sent_at = match impairment.bandwidth_kilobyte_per_sec {
+            Some(bandwidth) => {
+                let transmission =
+                    Duration::from_secs_f64(length as f64 / (bandwidth.max(1) * 1024) as f64);
+                self.free_at = self.free_at.max(now) + transmission;
+                self.free_at
+            }
+            None => now,
+        };
+        let copies = if self.rng.gen_bool(impairment.duplicate) { 2 } else { 1 };
+        (0..copies)
+            .map(|_| {
+                let delay_millis =
+                    impairment.delay_millis + self.rng.gen_range(0..=impairment.jitter_millis);
+                let arrival = sent_at + Duration::from_millis(delay_millis);
+                if self.rng.gen_bool(impairment.reorder) {
+                    return arrival + Duration::from_millis(impairment.reorder_delay_millis);
+                }
+                self.last_arrival = self.last_arrival.max(arrival);
+                self.last_arrival
+            })
+            .collect()
+    }
+}
+
+/// Sends the `packets` of one link from `socket` to `target`, each once it arrives. Packets that
+/// arrive at the same time are sent in the order they were handed over, and packets that are
+/// already due are sent right away.
+async fn forward(
+    socket: Arc<UdpSocket>,
+    target: SocketAddr,
+    mut packets: mpsc::UnboundedReceiver<(Instant, Vec<u8>)>,
+) {
+    let mut queue = BinaryHeap::<Scheduled>::new();
+    let mut sequence = 0u64;
+    loop {
+        let now = Instant::now();
+        while let Some(next) = queue.peek_mut() {
+            let Reverse((arrival, ..)) = &*next;
+            if *arrival > now {
+                break;
+            }
+            let Reverse((_, _, packet)) = PeekMut::pop(next);
+            // Like on a real network, packets that can't be sent are dropped.
+            let _ = socket.send_to(&packet, target).await;
+        }
+        let next_arrival = queue.peek().map(|Reverse((arrival, ..))| *arrival);
+        let next_due = tokio::time::sleep_until(next_arrival.unwrap_or(now));
+        tokio::select! {
+            Some((arrival, packet)) = packets.recv() => {
+                queue.push(Reverse((arrival, sequence, packet)));
+                sequence += 1;
+            }
+            _ = next_due, if next_arrival.is_some() => {}
+            else => return,
+        }
+    }
+}
+
+/// Forwards the packets that come back from the listening side to `client`.
+async fn relay_to_client(
+    upstream_socket: Arc<UdpSocket>,
+    client_socket: Arc<UdpSocket>,
+    client: SocketAddr,
+    impairment: Impairment,
+) -> io::Result<()> {
+    let mut link = Link::new(impairment, client_socket, client);
+    let mut buffer = vec![0u8; 65536];
+    loop {
+        let (length, _) = upstream_socket.recv_from(&mut buffer).await?;
+        link.send(&buffer[..length]);
+    }
+}
+
+/// Forwards UDP packets between dialing sides and the listening side, impairing them on the way in
+/// both directions. Every dialing side gets its own socket towards the listening side, like
+/// behind a NAT, and its own link in each direction.
+pub async fn run_proxy(args: &ProxyArgs) -> io::Result<()> {
+    let client_socket = Arc::new(UdpSocket::bind(args.listen_address).await?);
+    let unspecified_address: SocketAddr = if args.upstream_address.is_ipv4() {
+        "0.0.0.0:0".parse().expect("Valid address")
+    } else {
+        "[::]:0".parse().expect("Valid address")
+    };
+    info!(
+        listen_address = %args.listen_address,
+        upstream_address = %args.upstream_address,
+        "Proxying"
+    );
+
+    let mut upstream_links = HashMap::<SocketAddr, Link>::new();
+    let mut buffer = vec![0u8; 65536];
+    loop {
+        let (length, client) = client_socket.recv_from(&mut buffer).await?;
+        let upstream_link = match upstream_links.get_mut(&client) {
+            Some(upstream_link) => upstream_link,
+            None => {
+                info!(%client, "New client");
+                let upstream_socket = Arc::new(UdpSocket::bind(unspecified_address).await?);
+                tokio::spawn(relay_to_client(
+                    upstream_socket.clone(),
+                    client_socket.clone(),
+                    client,
+                    args.impairment,
+                ));
+                upstream_links.entry(client).or_insert(Link::new(
+                    args.impairment,
+                    upstream_socket,
+                    args.upstream_address,
+                ))
+            }
+        };
+        upstream_link.send(&buffer[..length]);
+    }
+}
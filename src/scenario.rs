@@ -0,0 +1,274 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use futures::StreamExt;
+use libp2p::identity::Keypair;
+use libp2p::request_response::{self, OutboundRequestId};
+use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::swarm::SwarmEvent;
+use libp2p::{Multiaddr, PeerId, Swarm};
+use serde::Deserialize;
+use tokio::time::Instant;
+use tracing::{info, info_span, Instrument};
+
+use crate::behaviour::{Behaviour, BehaviourEvent};
+use crate::codec::{Codec, Framing, Request};
+use crate::logging::log_swarm_event;
+use crate::mitigation::Mitigations;
+use crate::node::{build_swarm, spawn_receiver, StopConditions, Transport};
+use crate::verdict::describe_close_cause;
+
+/// A sequence of steps the sending side takes against a receiving side in this process, along
+/// with what it expects to happen. Read from a TOML file such as:
+///
+/// ```toml
+/// idle_connection_timeout_millis = 100
+///
+/// [[steps]]
+/// step = "dial"
+///
+/// [[steps]]
+/// step = "send_request"
+/// size_in_kilobyte = 500
+/// count = 3
+///
+/// [[steps]]
+/// step = "expect_close"
+/// within_millis = 1000
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default = "default_idle_connection_timeout_millis")]
+    pub idle_connection_timeout_millis: u64,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Step {
+    /// Dial the receiving side and wait until the connection is established.
+    Dial,
+    /// Let the swarm run for a while.
+    Wait { millis: u64 },
+    /// Send `count` requests for a response of `size_in_kilobyte` each.
+    SendRequest {
+        size_in_kilobyte: u64,
+        #[serde(default = "default_count")]
+        count: u64,
+    },
+    /// Expect a response to arrive, or to have arrived since the last expected one. Fails right
+    /// away if no request is waiting for one.
+    ExpectResponse {
+        #[serde(default = "default_within_millis")]
+        within_millis: u64,
+    },
+    /// Expect the connection to be closed, or to have been closed since the last expected close.
+    ExpectClose { within_millis: u64 },
+    /// Close the connection from the sending side and wait until it's closed.
+    Disconnect,
+}
+
+fn default_idle_connection_timeout_millis() -> u64 {
+    100
+}
+
+fn default_count() -> u64 {
+    1
+}
+
+fn default_within_millis() -> u64 {
+    10000
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+/// The step at which a scenario didn't go as expected.
+#[derive(Debug)]
+pub struct StepFailure {
+    /// Index of the step, starting from 1.
+    pub index: usize,
+    pub step: Step,
+    pub message: String,
+}
+
+/// The sending side of a scenario, and what happened on it that no step expected yet.
+struct Executor {
+    swarm: Swarm<Behaviour>,
+    listen_address: Multiaddr,
+    peer: Option<PeerId>,
+    outstanding: HashSet<OutboundRequestId>,
+    unexpected_responses: u64,
+    unexpected_closes: Vec<String>,
+    failures: Vec<String>,
+}
+
+impl Executor {
+    /// Handles the events of the swarm until `deadline`. Returns early once `done` holds.
+    async fn run_until(&mut self, deadline: Instant, done: impl Fn(&Self) -> bool) {
+        while !done(self) {
+            let Ok(event) = tokio::time::timeout_at(deadline, self.swarm.select_next_some()).await
+            else {
+                return;
+            };
+            log_swarm_event(&event);
+            match event {
+                SwarmEvent::ConnectionEstablished { peer_id, .. } => self.peer = Some(peer_id),
+                SwarmEvent::ConnectionClosed { num_established: 0, cause, .. } => {
+                    self.peer = None;
+                    self.unexpected_closes.push(describe_close_cause(cause.as_ref()));
+                }
+                SwarmEvent::OutgoingConnectionError { error, .. } => {
+                    self.failures.push(format!("Dialing failed: {error}"))
+                }
+                SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
+                    request_response::Event::Message {
+                        message: request_response::Message::Response { request_id, .. },
+                        ..
+                    },
+                )) => {
+                    self.outstanding.remove(&request_id);
+                    self.unexpected_responses += 1;
+                }
+                SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
+                    request_response::Event::OutboundFailure { request_id, error, .. },
+                )) => {
+                    self.outstanding.remove(&request_id);
+                    self.failures.push(format!("Request failed: {error}"));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn take_failure(&mut self) -> Result<(), String> {
+        match self.failures.drain(..).next() {
+            Some(failure) => Err(failure),
+            None => Ok(()),
+        }
+    }
+
+    async fn run_step(&mut self, step: &Step) -> Result<(), String> {
+        match step {
+            Step::Dial => {
+                self.swarm
+                    .dial(DialOpts::unknown_peer_id().address(self.listen_address.clone()).build())
+                    .map_err(|error| format!("Dialing failed: {error}"))?;
+                let deadline = Instant::now() + Duration::from_millis(default_within_millis());
+                self.run_until(deadline, |executor| {
+                    executor.peer.is_some() || !executor.failures.is_empty()
+                })
+                .await;
+                self.take_failure()?;
+                if self.peer.is_none() {
+                    return Err("The connection wasn't established".to_owned());
+                }
+            }
+            Step::Wait { millis } => {
+                self.run_until(Instant::now() + Duration::from_millis(*millis), |_| false).await;
+            }
+            Step::SendRequest { size_in_kilobyte, count } => {
+                let peer = self.peer.ok_or("Not connected")?;
+                for _ in 0..*count {
+                    let request_id = self
+                        .swarm
+                        .behaviour_mut()
+                        .request_response
+                        .send_request(&peer, Request::new(0, *size_in_kilobyte));
+                    self.outstanding.insert(request_id);
+                }
+            }
+            Step::ExpectResponse { within_millis } => {
+                if self.unexpected_responses == 0 && self.outstanding.is_empty() {
+                    return Err("No request is waiting for a response".to_owned());
+                }
+                let deadline = Instant::now() + Duration::from_millis(*within_millis);
+                self.run_until(deadline, |executor| {
+                    executor.unexpected_responses > 0
+                        || !executor.failures.is_empty()
+                        || !executor.unexpected_closes.is_empty()
+                })
+                .await;
+                if self.unexpected_responses > 0 {
+                    self.unexpected_responses -= 1;
+                    return Ok(());
+                }
+                self.take_failure()?;
+                if let Some(cause) = self.unexpected_closes.pop() {
+                    return Err(format!("The connection was {cause} before the response arrived"));
+                }
+                return Err(format!("No response arrived within {within_millis}ms"));
+            }
+            Step::ExpectClose { within_millis } => {
+                let deadline = Instant::now() + Duration::from_millis(*within_millis);
+                self.run_until(deadline, |executor| !executor.unexpected_closes.is_empty()).await;
+                if self.unexpected_closes.is_empty() {
+                    return Err(format!("The connection wasn't closed within {within_millis}ms"));
+                }
+                self.unexpected_closes.remove(0);
+            }
+            Step::Disconnect => {
+                let peer = self.peer.ok_or("Not connected")?;
+                self.swarm.disconnect_peer_id(peer).map_err(|()| "Not connected")?;
+                let deadline = Instant::now() + Duration::from_millis(default_within_millis());
+                self.run_until(deadline, |executor| executor.peer.is_none()).await;
+                if self.peer.is_some() {
+                    return Err("The connection wasn't closed".to_owned());
+                }
+                self.unexpected_closes.pop();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs `scenario` against a receiving side in this process, connected over an in-memory
+/// transport. Both sides use checksummed framing, so that requests can ask for responses of
+/// different sizes.
+pub async fn run_scenario(scenario: &Scenario) -> Result<(), StepFailure> {
+    let codec = Codec::new(0).with_framing(Framing::Checksummed);
+    let idle_connection_timeout = Duration::from_millis(scenario.idle_connection_timeout_millis);
+    let (listen_address, receiver_task) = spawn_receiver(
+        codec.clone(),
+        idle_connection_timeout,
+        Mitigations::default(),
+        StopConditions::default(),
+    )
+    .await;
+
+    let mut executor = Executor {
+        swarm: build_swarm(
+            Transport::Memory,
+            Keypair::generate_ed25519(),
+            codec,
+            idle_connection_timeout,
+            Mitigations::default(),
+        ),
+        listen_address,
+        peer: None,
+        outstanding: HashSet::new(),
+        unexpected_responses: 0,
+        unexpected_closes: Vec::new(),
+        failures: Vec::new(),
+    };
+    let mut result = Ok(());
+    for (index, step) in scenario.steps.iter().enumerate() {
+        info!(step = index + 1, ?step, "Running step");
+        let step_result =
+            executor.run_step(step).instrument(info_span!("node", role = "sender")).await;
+        if let Err(message) = step_result {
+            result = Err(StepFailure { index: index + 1, step: step.clone(), message });
+            break;
+        }
+    }
+
+    receiver_task.abort();
+    result
+}
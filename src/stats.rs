@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use libp2p::request_response::OutboundRequestId;
+use serde::Serialize;
+
+use crate::codec::Arrival;
+
+/// The timing of a single request, relative to when it was sent.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Transfer {
+    pub bytes: u64,
+    pub time_to_first_byte_millis: f64,
+    pub time_to_last_byte_millis: f64,
+    /// Bytes of the response over the time from sending the request to the last byte.
+    pub throughput_mb_per_sec: f64,
+}
+
+impl Transfer {
+    fn new(sent_at: Instant, arrival: Arrival) -> Self {
+        let time_to_last_byte = arrival.last_byte.saturating_duration_since(sent_at);
+        Self {
+            bytes: arrival.bytes,
+            time_to_first_byte_millis: millis(
+                arrival.first_byte.saturating_duration_since(sent_at),
+            ),
+            time_to_last_byte_millis: millis(time_to_last_byte),
+            throughput_mb_per_sec: arrival.bytes as f64
+                / 1_000_000.0
+                / time_to_last_byte.as_secs_f64().max(f64::EPSILON),
+        }
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+impl Percentiles {
+    /// Computes nearest-rank percentiles. Returns `None` if there are no values.
+    fn of(mut values: Vec<f64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f64::total_cmp);
+        let at = |percentile: f64| {
+            let rank = (percentile / 100.0 * values.len() as f64).ceil() as usize;
+            values[rank.clamp(1, values.len()) - 1]
+        };
+        Some(Self { p50: at(50.0), p90: at(90.0), p99: at(99.0) })
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StatsSummary {
+    pub transfers: Vec<Transfer>,
+    pub total_bytes: u64,
+    /// Only set when more than one transfer completed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_to_last_byte_millis: Option<Percentiles>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub throughput_mb_per_sec: Option<Percentiles>,
+}
+
+impl StatsSummary {
+    pub fn print(&self) {
+        for (index, transfer) in self.transfers.iter().enumerate() {
+            println!(
+                "Transfer {}: {} bytes, first byte after {:.1}ms, last byte after {:.1}ms, {:.2} \
+                 MB/s",
+                index + 1,
+                transfer.bytes,
+                transfer.time_to_first_byte_millis,
+                transfer.time_to_last_byte_millis,
+                transfer.throughput_mb_per_sec
+            );
+        }
+        if let Some(latency) = self.time_to_last_byte_millis {
+            println!(
+                "Time to last byte: p50 {:.1}ms, p90 {:.1}ms, p99 {:.1}ms",
+                latency.p50, latency.p90, latency.p99
+            );
+        }
+        if let Some(throughput) = self.throughput_mb_per_sec {
+            println!(
+                "Throughput: p50 {:.2} MB/s, p90 {:.2} MB/s, p99 {:.2} MB/s",
+                throughput.p50, throughput.p90, throughput.p99
+            );
+        }
+    }
+}
+
+/// Records when each request of the sending side was sent and when its response arrived.
+#[derive(Default)]
+pub struct TransferStats {
+    sent_at: HashMap<OutboundRequestId, Instant>,
+    transfers: Vec<Transfer>,
+}
+
+impl TransferStats {
+    pub fn on_sent(&mut self, request_id: OutboundRequestId) {
+        self.sent_at.insert(request_id, Instant::now());
+    }
+
+    pub fn on_response(&mut self, request_id: &OutboundRequestId, arrival: Option<Arrival>) {
+        let sent_at = self.sent_at.remove(request_id);
+        if let (Some(sent_at), Some(arrival)) = (sent_at, arrival) {
+            self.transfers.push(Transfer::new(sent_at, arrival));
+        }
+    }
+
+    /// Summarizes the completed transfers. Returns `None` if none completed.
+    pub fn summary(&self) -> Option<StatsSummary> {
+        if self.transfers.is_empty() {
+            return None;
+        }
+        let (time_to_last_byte_millis, throughput_mb_per_sec) = if self.transfers.len() > 1 {
+            (
+                Percentiles::of(
+                    self.transfers.iter().map(|t| t.time_to_last_byte_millis).collect(),
+                ),
+                Percentiles::of(self.transfers.iter().map(|t| t.throughput_mb_per_sec).collect()),
+            )
+        } else {
+            (None, None)
+        };
+        Some(StatsSummary {
+            transfers: self.transfers.clone(),
+            total_bytes: self.transfers.iter().map(|transfer| transfer.bytes).sum(),
+            time_to_last_byte_millis,
+            throughput_mb_per_sec,
+        })
+    }
+}
@@ -0,0 +1,207 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::ValueEnum;
+
+use crate::codec::{Codec, Request};
+use crate::mitigation::Mitigations;
+use crate::node::{run_in_process, Sender, Workload};
+use crate::verdict::Verdict;
+
+/// An inclusive range of values, written as `START..END` or `START..END:STEP`.
+#[derive(Clone, Debug)]
+pub struct SweepRange {
+    start: u64,
+    end: u64,
+    step: u64,
+}
+
+impl SweepRange {
+    pub fn values(&self) -> Vec<u64> {
+        (self.start..=self.end).step_by(self.step as usize).collect()
+    }
+}
+
+impl FromStr for SweepRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, step) = match s.split_once(':') {
+            Some((range, step)) => {
+                (range, step.parse().map_err(|_| format!("Invalid step in range {s}"))?)
+            }
+            None => (s, 1),
+        };
+        let (start, end) = match range.split_once("..") {
+            Some((start, end)) => (
+                start.parse().map_err(|_| format!("Invalid start in range {s}"))?,
+                end.parse().map_err(|_| format!("Invalid end in range {s}"))?,
+            ),
+            None => {
+                let value = range.parse().map_err(|_| format!("Invalid range {s}"))?;
+                (value, value)
+            }
+        };
+        if step == 0 || start > end {
+            return Err(format!("Range {s} is empty"));
+        }
+        Ok(Self { start, end, step })
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SweepFormat {
+    Table,
+    Csv,
+}
+
+#[derive(clap::Args)]
+pub struct SweepArgs {
+    /// Message sizes to try, in KB.
+    #[arg(short, long, default_value = "1..1024:64")]
+    message_sizes_in_kilobyte: SweepRange,
+
+    /// Idle connection timeouts to try, in milliseconds.
+    #[arg(short = 't', long, default_value = "100")]
+    idle_connection_timeouts_millis: SweepRange,
+
+    /// If active, binary-search the smallest message size that triggers the bug for every idle
+    /// connection timeout instead of running every grid point. Assumes that the bug keeps
+    /// occurring for larger messages once it occurred.
+    #[arg(short, long)]
+    binary_search: bool,
+
+    /// Amount of time each run waits for the outcome before giving up.
+    #[arg(long, default_value_t = 60000)]
+    run_timeout_millis: u64,
+
+    /// Format in which the results are printed.
+    #[arg(short, long, value_enum, default_value_t = SweepFormat::Table)]
+    format: SweepFormat,
+}
+
+/// The outcome of a single grid point.
+pub struct SweepPoint {
+    pub message_size_in_kilobyte: u64,
+    pub idle_connection_timeout_millis: u64,
+    pub verdict: Verdict,
+}
+
+/// The smallest message size that triggered the bug for an idle connection timeout, if any.
+pub struct SweepBoundary {
+    pub idle_connection_timeout_millis: u64,
+    pub smallest_failing_message_size_in_kilobyte: Option<u64>,
+}
+
+async fn run_point(
+    message_size_in_kilobyte: u64,
+    idle_connection_timeout_millis: u64,
+    run_timeout: Duration,
+) -> SweepPoint {
+    let mut sender = Sender::new(Workload::single(Request::new(0, message_size_in_kilobyte)));
+    let verdict = tokio::time::timeout(
+        run_timeout,
+        run_in_process(
+            Codec::new(message_size_in_kilobyte),
+            &mut sender,
+            Duration::from_millis(idle_connection_timeout_millis),
+            Mitigations::default(),
+        ),
+    )
+    .await
+    .unwrap_or(Verdict::Timeout);
+    SweepPoint { message_size_in_kilobyte, idle_connection_timeout_millis, verdict }
+}
+
+pub async fn run_grid(args: &SweepArgs) -> Vec<SweepPoint> {
+    let run_timeout = Duration::from_millis(args.run_timeout_millis);
+    let mut points = Vec::new();
+    for idle_connection_timeout_millis in args.idle_connection_timeouts_millis.values() {
+        for message_size_in_kilobyte in args.message_sizes_in_kilobyte.values() {
+            points.push(
+                run_point(message_size_in_kilobyte, idle_connection_timeout_millis, run_timeout)
+                    .await,
+            );
+        }
+    }
+    points
+}
+
+pub async fn run_binary_search(args: &SweepArgs) -> Vec<SweepBoundary> {
+    let run_timeout = Duration::from_millis(args.run_timeout_millis);
+    let message_sizes = args.message_sizes_in_kilobyte.values();
+    let mut boundaries = Vec::new();
+    for idle_connection_timeout_millis in args.idle_connection_timeouts_millis.values() {
+        let (mut low, mut high) = (0, message_sizes.len());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let point =
+                run_point(message_sizes[middle], idle_connection_timeout_millis, run_timeout).await;
+            if point.verdict.bug_occurred() {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+        boundaries.push(SweepBoundary {
+            idle_connection_timeout_millis,
+            smallest_failing_message_size_in_kilobyte: message_sizes.get(low).copied(),
+        });
+    }
+    boundaries
+}
+
+pub async fn run_sweep(args: &SweepArgs) {
+    if args.binary_search {
+        let boundaries = run_binary_search(args).await;
+        match args.format {
+            SweepFormat::Table => {
+                println!("{:>12} {:>20}", "timeout_ms", "smallest_failing_kb");
+                for boundary in boundaries {
+                    let size = boundary
+                        .smallest_failing_message_size_in_kilobyte
+                        .map_or_else(|| "-".to_owned(), |size| size.to_string());
+                    println!("{:>12} {:>20}", boundary.idle_connection_timeout_millis, size);
+                }
+            }
+            SweepFormat::Csv => {
+                println!(
+                    "idle_connection_timeout_millis,smallest_failing_message_size_in_kilobyte"
+                );
+                for boundary in boundaries {
+                    let size = boundary
+                        .smallest_failing_message_size_in_kilobyte
+                        .map_or_else(String::new, |size| size.to_string());
+                    println!("{},{}", boundary.idle_connection_timeout_millis, size);
+                }
+            }
+        }
+        return;
+    }
+
+    let points = run_grid(args).await;
+    match args.format {
+        SweepFormat::Table => {
+            println!("{:>12} {:>12} {:<24}", "timeout_ms", "size_kb", "verdict");
+            for point in points {
+                println!(
+                    "{:>12} {:>12} {:<24}",
+                    point.idle_connection_timeout_millis,
+                    point.message_size_in_kilobyte,
+                    point.verdict.name()
+                );
+            }
+        }
+        SweepFormat::Csv => {
+            println!("idle_connection_timeout_millis,message_size_in_kilobyte,verdict");
+            for point in points {
+                println!(
+                    "{},{},{}",
+                    point.idle_connection_timeout_millis,
+                    point.message_size_in_kilobyte,
+                    point.verdict.name()
+                );
+            }
+        }
+    }
+}
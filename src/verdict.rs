@@ -0,0 +1,190 @@
+use clap::ValueEnum;
+use libp2p::swarm::ConnectionError;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::stats::StatsSummary;
+
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// The outcome of a run. All but `Served` are seen by the sending side.
+#[derive(Debug, Serialize)]
+#[serde(tag = "verdict", rename_all = "snake_case")]
+pub enum Verdict {
+    /// All the responses arrived in full. The bug did not occur.
+    ResponseReceived,
+    /// The connection was closed before all the responses arrived. The bug occurred.
+    ClosedBeforeResponse { completed: u64, requested: u64, cause: String },
+    /// The request-response behaviour gave up on a request after its request timeout.
+    RequestTimeout,
+    /// Dialing the peer failed.
+    DialFailure { error: String },
+    /// The remote doesn't support the request-response protocol.
+    UnsupportedProtocols,
+    /// Reading the response or writing the request failed, e.g. because the stream was reset or
+    /// the response was truncated or corrupted.
+    StreamIo { error: String },
+    /// Neither a response nor a close happened within the run timeout.
+    Timeout,
+    /// The run was interrupted with Ctrl-C before the sending side knew whether the bug occurred.
+    Interrupted,
+    /// The receiving side stopped after serving requests.
+    Served { responses_sent: u64, inbound_failures: u64 },
+    /// The dialed peer had a different PeerId than the one in the dial address.
+    WrongPeerId {
+        #[serde(serialize_with = "serialize_display")]
+        expected: PeerId,
+        #[serde(serialize_with = "serialize_display")]
+        obtained: PeerId,
+    },
+    /// The outcomes with each of several peers.
+    PerPeer { peers: Vec<PeerVerdict> },
+}
+
+/// The outcome with one of several peers.
+#[derive(Debug, Serialize)]
+pub struct PeerVerdict {
+    /// The PeerId of the peer, or the address it was dialed at if the dial failed before its
+    /// PeerId was known.
+    pub peer: String,
+    #[serde(flatten)]
+    pub verdict: Verdict,
+}
+
+/// Describes why a connection was closed, given the cause from `SwarmEvent::ConnectionClosed`.
+pub fn describe_close_cause(cause: Option<&ConnectionError>) -> String {
+    match cause {
+        None => "closed by this node".to_owned(),
+        Some(ConnectionError::KeepAliveTimeout) => {
+            "closed by this node after the idle connection timeout".to_owned()
+        }
+        Some(ConnectionError::IO(error)) => format!("closed by the remote or the network: {error}"),
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    #[serde(flatten)]
+    verdict: &'a Verdict,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<&'a StatsSummary>,
+}
+
+fn serialize_display<T: std::fmt::Display, S: serde::Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+impl Verdict {
+    /// The code the process exits with. 2 is skipped because clap uses it for usage errors. With
+    /// several peers, it's 1 if the bug occurred with any of them, and otherwise the first code
+    /// that isn't 0.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Verdict::PerPeer { .. } if self.bug_occurred() => 1,
+            Verdict::PerPeer { peers } => peers
+                .iter()
+                .map(|peer| peer.verdict.exit_code())
+                .find(|exit_code| *exit_code != 0)
+                .unwrap_or(0),
+            Verdict::ResponseReceived | Verdict::Served { .. } => 0,
+            Verdict::ClosedBeforeResponse { .. } => 1,
+            Verdict::DialFailure { .. } => 3,
+            Verdict::Timeout => 4,
+            Verdict::WrongPeerId { .. } => 5,
+            Verdict::RequestTimeout => 6,
+            Verdict::UnsupportedProtocols => 7,
+            Verdict::StreamIo { .. } => 8,
+            // The conventional exit code of a process killed by SIGINT.
+            Verdict::Interrupted => 130,
+        }
+    }
+
+    /// A short identifier of the verdict, matching its JSON tag.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Verdict::ResponseReceived => "response_received",
+            Verdict::ClosedBeforeResponse { .. } => "closed_before_response",
+            Verdict::RequestTimeout => "request_timeout",
+            Verdict::DialFailure { .. } => "dial_failure",
+            Verdict::UnsupportedProtocols => "unsupported_protocols",
+            Verdict::StreamIo { .. } => "stream_io",
+            Verdict::Timeout => "timeout",
+            Verdict::Interrupted => "interrupted",
+            Verdict::Served { .. } => "served",
+            Verdict::WrongPeerId { .. } => "wrong_peer_id",
+            Verdict::PerPeer { .. } => "per_peer",
+        }
+    }
+
+    pub fn bug_occurred(&self) -> bool {
+        match self {
+            Verdict::ClosedBeforeResponse { .. } => true,
+            Verdict::PerPeer { peers } => peers.iter().any(|peer| peer.verdict.bug_occurred()),
+            _ => false,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Verdict::ResponseReceived => "The bug did not occur, we got the response. Try to run \
+                                          with a larger message or smaller timeout to make the \
+                                          bug occur"
+                .to_owned(),
+            Verdict::ClosedBeforeResponse { completed, requested, cause } => format!(
+                "The bug occurred! The connection was closed before we got the response \
+                 ({completed} out of {requested} responses arrived). The connection was {cause}"
+            ),
+            Verdict::RequestTimeout => {
+                "The request timed out in the request-response behaviour".to_owned()
+            }
+            Verdict::DialFailure { error } => format!("Dialing the peer failed: {error}"),
+            Verdict::UnsupportedProtocols => {
+                "The request failed because the remote doesn't support the protocol".to_owned()
+            }
+            Verdict::StreamIo { error } => {
+                format!("The request failed because of an error on its stream: {error}")
+            }
+            Verdict::Timeout => {
+                "Timed out before getting the response or a closed connection".to_owned()
+            }
+            Verdict::Interrupted => "Interrupted before getting the response".to_owned(),
+            Verdict::Served { responses_sent, inbound_failures } => format!(
+                "Sent {responses_sent} responses, {inbound_failures} requests failed before \
+                 being answered"
+            ),
+            Verdict::WrongPeerId { expected, obtained } => format!(
+                "Dialed the wrong peer: expected PeerId {expected} but the remote has {obtained}"
+            ),
+            Verdict::PerPeer { peers } => peers
+                .iter()
+                .map(|peer| format!("{}: {}", peer.peer, peer.verdict.message()))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    /// Prints the verdict, along with the transfer statistics of the run if there are any.
+    pub fn report(&self, stats: Option<&StatsSummary>, output: OutputFormat) {
+        match output {
+            OutputFormat::Text => {
+                if let Some(stats) = stats {
+                    stats.print();
+                }
+                println!("{}", self.message());
+            }
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string(&Report { verdict: self, stats })
+                    .expect("Error while serializing the verdict")
+            ),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Pins whether the connection is closed before the response arrives, for a matrix of response
+//! sizes and idle connection timeouts. The receiving side writes every 1KB of the response after a
+//! fixed delay, so that how long the transfer takes compared to the idle connection timeout doesn't
+//! depend on the machine. If a libp2p upgrade changes the outcome of any case, update `CASES`
+//! along with the upgrade.
+//!
+//! With libp2p-swarm 0.44, a connection isn't idle while it has an active stream, so a transfer
+//! that outlasts the idle connection timeout still completes. Only a zero idle connection timeout
+//! closes the connection before the response, as soon as it has no stream.
+
+use std::time::Duration;
+
+use libp2p_bug_example::codec::{Codec, Request, Throttle};
+use libp2p_bug_example::mitigation::Mitigations;
+use libp2p_bug_example::node::{run_in_process, Sender, Workload};
+use libp2p_bug_example::verdict::Verdict;
+
+/// Delay before every 1KB of the response is written.
+const CHUNK_DELAY: Duration = Duration::from_millis(10);
+
+const RUN_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Outcome {
+    ResponseDelivered,
+    ClosedBeforeResponse,
+}
+
+struct Case {
+    message_size_in_kilobyte: u64,
+    idle_connection_timeout_millis: u64,
+    expected: Outcome,
+}
+
+const CASES: &[Case] = &[
+    // The transfer takes 10ms.
+    Case {
+        message_size_in_kilobyte: 1,
+        idle_connection_timeout_millis: 0,
+        expected: Outcome::ClosedBeforeResponse,
+    },
+    Case {
+        message_size_in_kilobyte: 1,
+        idle_connection_timeout_millis: 100,
+        expected: Outcome::ResponseDelivered,
+    },
+    Case {
+        message_size_in_kilobyte: 1,
+        idle_connection_timeout_millis: 10000,
+        expected: Outcome::ResponseDelivered,
+    },
+    // The transfer takes 640ms.
+    Case {
+        message_size_in_kilobyte: 64,
+        idle_connection_timeout_millis: 0,
+        expected: Outcome::ClosedBeforeResponse,
+    },
+    Case {
+        message_size_in_kilobyte: 64,
+        idle_connection_timeout_millis: 100,
+        expected: Outcome::ResponseDelivered,
+    },
+    Case {
+        message_size_in_kilobyte: 64,
+        idle_connection_timeout_millis: 10000,
+        expected: Outcome::ResponseDelivered,
+    },
+    // The transfer takes 2.56s.
+    Case {
+        message_size_in_kilobyte: 256,
+        idle_connection_timeout_millis: 0,
+        expected: Outcome::ClosedBeforeResponse,
+    },
+    Case {
+        message_size_in_kilobyte: 256,
+        idle_connection_timeout_millis: 100,
+        expected: Outcome::ResponseDelivered,
+    },
+    Case {
+        message_size_in_kilobyte: 256,
+        idle_connection_timeout_millis: 10000,
+        expected: Outcome::ResponseDelivered,
+    },
+];
+
+/// Runs the exchange in this process, with both swarms built by `build_swarm`, and returns its
+/// verdict.
+async fn exchange(message_size_in_kilobyte: u64, idle_connection_timeout_millis: u64) -> Verdict {
+    let codec =
+        Codec::new(message_size_in_kilobyte).with_write_throttle(Throttle::ChunkDelay(CHUNK_DELAY));
+    let mut sender = Sender::new(Workload::single(Request::new(0, message_size_in_kilobyte)));
+    tokio::time::timeout(
+        RUN_TIMEOUT,
+        run_in_process(
+            codec,
+            &mut sender,
+            Duration::from_millis(idle_connection_timeout_millis),
+            Mitigations::default(),
+        ),
+    )
+    .await
+    .unwrap_or_else(|_| sender.timeout_verdict())
+}
+
+#[tokio::test]
+async fn early_close_matrix() {
+    let mut mismatches = Vec::new();
+    for case in CASES {
+        let verdict =
+            exchange(case.message_size_in_kilobyte, case.idle_connection_timeout_millis).await;
+        let outcome = match verdict {
+            Verdict::ResponseReceived => Outcome::ResponseDelivered,
+            Verdict::ClosedBeforeResponse { .. } => Outcome::ClosedBeforeResponse,
+            verdict => panic!(
+                "{}KB with an idle connection timeout of {}ms ended in neither: {:?}",
+                case.message_size_in_kilobyte, case.idle_connection_timeout_millis, verdict
+            ),
+        };
+        if outcome != case.expected {
+            mismatches.push(format!(
+                "{}KB with an idle connection timeout of {}ms: expected {:?}, got {:?}",
+                case.message_size_in_kilobyte,
+                case.idle_connection_timeout_millis,
+                case.expected,
+                outcome
+            ));
+        }
+    }
+    assert!(mismatches.is_empty(), "The early-close behavior changed:\n{}", mismatches.join("\n"));
+}